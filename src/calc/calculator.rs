@@ -1,138 +1,1600 @@
-use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_2, LN_2, LOG10_2};
+use std::sync::Arc;
 
-use crate::calc::parser::{BinaryOperator, Constant, Parser, Token, UnaryOperator};
-use crate::log::{Level, log};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
 
-// Thread-local storage for the last computed result for reuse in expressions
-thread_local! {
-    static LAST_RESULT: RefCell<Option<f64>> = RefCell::new(None);
+use crate::calc::complex;
+use crate::calc::context::{Context, UserFunction};
+use crate::calc::decimal::{self, Decimal};
+use crate::calc::error::{Error, ParseError, math_error};
+use crate::calc::integer::{self, MAX_BITS};
+use crate::calc::matrix;
+use crate::calc::number_theory;
+use crate::calc::parser::{
+    BinaryOperator, BinaryToken, Constant, Function, IndexToken, Parser, SeriesToken, Token,
+    UnaryOperator, UserCallToken,
+};
+use crate::calc::rational;
+use crate::calc::settings::{AngleUnit, ComplexFormat, Format, Mode, Settings};
+use crate::calc::special;
+use crate::calc::statistics;
+use crate::calc::value::Value;
+
+// Deepest allowed nesting of user function calls, so runaway recursion is an error
+const MAX_CALL_DEPTH: usize = 256;
+// Most terms of sums and products like sum(k, 1, 100, k^2) in a statement, counting those of
+// nested ones, so runaway loops are an error
+const MAX_SERIES_TERMS: u32 = 1_000_000;
+// Largest n for identity(n), which has n^2 entries
+const MAX_MATRIX_SIZE: usize = 1000;
+
+/// Evaluates expressions, keeping the last result, variables, user functions and
+/// settings between calls.
+#[derive(Default)]
+pub struct Calculator {
+    context: Context,
 }
 
-// Public function to evaluate a mathematical expression string
-// Parses the input, solves the expression tree, and stores the result
-pub fn evaluate(line: String) -> Result<f64, String> {
-    let mut parser = Parser::new(&line);
-    let root = match parser.parse() {
-        Ok(token) => token,
-        Err(e) => return Err(format!("Syntax Error: {}", e)),
-    };
-    let result = solve(root);
+impl Calculator {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    // Check if result is NaN (indicates an error occurred during calculation)
-    if result.is_nan() {
-        return Err("Mathematical error occurred".to_string());
+    /// Evaluates `input` and returns the result of its last statement.
+    /// Input without any statement producing a result, like a definition, is
+    /// [`Error::NoResult`].
+    pub fn eval(&mut self, input: &str) -> Result<Value, Error> {
+        self.eval_all(input)?.pop().ok_or(Error::NoResult)
     }
 
-    // Save the result for future use in subsequent expressions
-    LAST_RESULT.with(|last_result| {
-        *last_result.borrow_mut() = Some(result);
-    });
+    /// Evaluates each statement of `input`, separated by `;` or a newline, in order and returns
+    /// their results, so later statements can build on earlier ones. Definitions produce no
+    /// result.
+    pub fn eval_all(&mut self, input: &str) -> Result<Vec<Value>, Error> {
+        let statements = self.parse(input)?;
+        let context = &mut self.context;
+        let mut results = Vec::with_capacity(statements.len());
 
-    Ok(result)
+        for root in statements {
+            // Definitions are stored and produce no result
+            if let Token::Definition(definition) = root {
+                let function = UserFunction {
+                    parameters: definition.parameters,
+                    body: *definition.body,
+                    source: definition.source,
+                };
+                context
+                    .functions
+                    .insert(definition.name, Arc::new(function));
+                continue;
+            }
+
+            context.series_terms = 0;
+            let result = solve(&root, context)?;
+
+            // Failed calculations are errors where they happen, NaN is left from infinities
+            if result.is_nan() {
+                return Err(math_error("result is not a number"));
+            }
+
+            // Save the result for future use in subsequent expressions
+            context.last_result = Some(result.clone());
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+
+    /// Parses `input` into one syntax tree per statement without evaluating it.
+    pub fn parse(&self, input: &str) -> Result<Vec<Token>, ParseError> {
+        let complex = complex_numbers(&self.context);
+        Parser::new(input, self.context.function_arities(), complex).parse()
+    }
+
+    /// The result of the last evaluated statement.
+    pub fn last_result(&self) -> Option<Value> {
+        self.context.last_result.clone()
+    }
+
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        self.context.variables.get(name).cloned()
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: impl Into<Value>) {
+        self.context
+            .variables
+            .insert(name.to_string(), value.into());
+    }
+
+    /// Definitions of all user functions as typed, sorted by name.
+    pub fn function_definitions(&self) -> Vec<String> {
+        let mut names: Vec<&String> = self.context.functions.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| self.context.functions[name].source.clone())
+            .collect()
+    }
+
+    /// Removes a user function, returning whether it existed.
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        self.context.functions.remove(name).is_some()
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.context.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.context.settings
+    }
+
+    /// Formats a result as configured by [`Settings::format`].
+    pub fn format(&self, value: &Value) -> String {
+        let settings = &self.context.settings;
+        match value {
+            Value::Rational(r) if settings.format == Format::Decimal && !r.is_integer() => {
+                Value::Number(value.to_f64()).to_string()
+            }
+            Value::Complex(z) if settings.complex == Some(ComplexFormat::Polar) => {
+                complex::polar(z)
+            }
+            Value::List(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| self.format(e)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            _ => value.to_string(),
+        }
+    }
 }
 
 // Recursive function to solve/evaluate the expression tree represented by Token
-fn solve(token: Token) -> f64 {
-    match token {
+// Math errors and unresolvable names are returned where they happen, NaN can only come from
+// floating point operations like inf - inf
+fn solve(token: &Token, context: &mut Context) -> Result<Value, Error> {
+    let result = match token {
         Token::Unary(t) => {
             // Evaluate the operand first
-            let operand = solve(*t.operand);
-
-            // Apply the unary operation
-            let result = match t.operation {
-                UnaryOperator::Factorial => {
-                    // Factorial: n! = n * (n-1) * ... * 1, for non-negative integers
-                    if operand < 0.0 || operand.fract() != 0.0 {
-                        log(
-                            Level::Warning,
-                            "Factorial of negative or non-integer number",
-                        );
-                        f64::NAN
-                    } else {
-                        // Compute factorial using product of range
-                        (1..=operand as u64).product::<u64>() as f64
-                    }
+            let operand = solve(&t.operand, context)?;
+
+            apply_unary(&t.operation, operand, context)?
+        }
+        // Returned as is, as each ? adds to the stack frame of solve in debug builds
+        Token::Binary(t) => return solve_binary(t, context),
+        Token::Call(t) if matches!(t.function, Function::If) => {
+            // Conditional: only the selected branch is evaluated
+            let condition = solve(&t.arguments[0], context)?;
+            let (then_branch, else_branch) = (&t.arguments[1], &t.arguments[2]);
+
+            if condition.is_nan() {
+                Value::Number(f64::NAN)
+            } else if !condition.is_zero() {
+                solve(then_branch, context)?
+            } else {
+                solve(else_branch, context)?
+            }
+        }
+        Token::Call(t) if matches!(t.function, Function::Max | Function::Min) => {
+            let arguments = solve_all(&t.arguments, context)?;
+            extremum(&t.function, arguments, context)?
+        }
+        Token::Call(t) => {
+            let arguments = solve_all(&t.arguments, context)?;
+            call(&t.function, arguments, context)?
+        }
+        Token::List(elements) => Value::List(solve_all(elements, context)?),
+        Token::Index(t) => return element(t, context),
+        Token::Series(t) => return series(t, context),
+        Token::Value(n) => number(n.clone(), context), // Literal number value
+        Token::Constant(Constant::I) => imaginary_unit(context)?,
+        Token::Constant(c) => {
+            // Mathematical constants, to the requested digits in decimal mode
+            match (c, decimal_digits(context)) {
+                (Constant::Pi, Some(digits)) => Value::Decimal(decimal::pi(digits)),
+                (Constant::E, Some(digits)) => Value::Decimal(decimal::e(digits)),
+                (Constant::Pi, None) => Value::Number(std::f64::consts::PI),
+                (Constant::E, None) => Value::Number(std::f64::consts::E),
+                (Constant::I, _) => unreachable!(),
+            }
+        }
+        Token::LastResult => {
+            // Retrieve the last computed result from the context
+            match &context.last_result {
+                Some(value) => value.clone(),
+                None => return Err(math_error("no last result available")),
+            }
+        }
+        Token::Variable(name) => match context.lookup(name) {
+            Some(value) => value,
+            None if name == "i" => imaginary_unit(context)?,
+            None => return Err(Error::UnknownVariable(name.clone())),
+        },
+        Token::Assignment(t) => {
+            let value = solve(&t.value, context)?;
+            // A failed calculation must not be stored
+            if !value.is_nan() {
+                context.variables.insert(t.name.clone(), value.clone());
+            }
+            value
+        }
+        Token::UserCall(t) => return call_user(t, context),
+        // Definitions only appear as statements and are stored by eval_all
+        Token::Definition(_) => unreachable!(),
+    };
+
+    Ok(result)
+}
+
+// Calls to user functions, kept out of solve like solve_binary
+fn call_user(t: &UserCallToken, context: &mut Context) -> Result<Value, Error> {
+    let function = match context.functions.get(&t.name) {
+        Some(function) => Arc::clone(function),
+        None => return Err(Error::UnknownFunction(t.name.clone())),
+    };
+    if function.parameters.len() != t.arguments.len() {
+        return Err(Error::WrongArgumentCount {
+            name: t.name.clone(),
+            expected: function.parameters.len(),
+            found: t.arguments.len(),
+        });
+    }
+    if context.frames.len() >= MAX_CALL_DEPTH {
+        return Err(Error::RecursionLimit);
+    }
+
+    // Arguments are evaluated in the caller's scope, then bound to the parameters
+    let arguments = solve_all(&t.arguments, context)?;
+    let frame: HashMap<String, Value> =
+        function.parameters.iter().cloned().zip(arguments).collect();
+
+    context.frames.push(frame);
+    let result = solve(&function.body, context);
+    context.frames.pop();
+    result
+}
+
+// Binary operators, kept out of solve so its stack frame stays small for deep recursion
+fn solve_binary(t: &BinaryToken, context: &mut Context) -> Result<Value, Error> {
+    let left = solve(&t.left, context)?;
+
+    // && and || short-circuit, so the right side is only evaluated when needed
+    match t.operation {
+        BinaryOperator::And if left.is_zero() => return Ok(truth(false, context)),
+        BinaryOperator::Or
+            if !left.is_zero() && !left.is_nan() && !matches!(left, Value::List(_)) =>
+        {
+            return Ok(truth(true, context));
+        }
+        _ => {}
+    }
+
+    let right = solve(&t.right, context)?;
+
+    if matches!(t.operation, BinaryOperator::MatrixMultiply) {
+        return matrix_product(left, right, context);
+    }
+    // A failed operand must not turn into a plain true/false
+    if (left.is_nan() || right.is_nan()) && t.operation.is_logical() {
+        return Ok(Value::Number(f64::NAN));
+    }
+
+    broadcast_binary(&t.operation, left, right, context)
+}
+
+// Checks the operand and handles angle units, then computes the operation
+fn apply_unary(
+    operation: &UnaryOperator,
+    operand: Value,
+    context: &Context,
+) -> Result<Value, Error> {
+    if let Value::List(elements) = operand {
+        return elements
+            .into_iter()
+            .map(|element| apply_unary(operation, element, context))
+            .collect::<Result<_, _>>()
+            .map(Value::List);
+    }
+    let operand = operand.expand();
+    check_pole(operation, &operand)?;
+    check_size(operation, &operand)?;
+    check_real_domain(operation, &operand, context)?;
+    let angle = context.settings.angle;
+    match operation {
+        UnaryOperator::Angle(unit) => convert_angle(operand, *unit, angle, context),
+        _ if operation.takes_angle() => {
+            if let Some(exact) = exact_trig(operation, &operand, angle, context)? {
+                return Ok(exact);
+            }
+            let radians = convert_angle(operand.clone(), angle, AngleUnit::Radians, context)?;
+            if let Value::Decimal(x) = &radians
+                && !decimal::is_reducible(x)
+            {
+                return Err(Error::Domain(format!(
+                    "{} argument is too large",
+                    trig_name(operation)
+                )));
+            }
+            check_trig_pole(operation, &operand, &radians, context)?;
+            unary_value(operation, radians, context)
+        }
+        _ if operation.returns_angle() => {
+            if let Some(exact) = exact_inverse_trig(operation, &operand, angle, context) {
+                return Ok(exact);
+            }
+            let radians = unary_value(operation, operand, context)?;
+            convert_angle(radians, AngleUnit::Radians, angle, context)
+        }
+        _ => unary_value(operation, operand, context),
+    }
+}
+
+// Decimals and fractions are used where possible, anything else is computed in floating point
+fn unary_value(
+    operation: &UnaryOperator,
+    operand: Value,
+    context: &Context,
+) -> Result<Value, Error> {
+    Ok(match &operand {
+        _ if let Some(digits) = decimal_digits(context)
+            && let Some(d) = to_decimal(&operand, digits)
+            && let Some(result) = decimal::unary(operation, &d, digits) =>
+        {
+            Value::Decimal(result)
+        }
+        Value::Integer(n) if let Some(exact) = integer::unary(operation, n) => {
+            Value::Integer(exact)
+        }
+        Value::Rational(r) if let Some(exact) = rational::unary(operation, r) => {
+            Value::Rational(exact)
+        }
+        Value::Complex(z) => complex::value(complex::unary(operation, *z)?),
+        _ if complex_numbers(context)
+            && complex::leaves_reals_unary(operation, operand.to_f64()) =>
+        {
+            complex::value(complex::unary(operation, operand.to_complex())?)
+        }
+        _ => {
+            let result = unary(operation, operand.to_f64())?;
+            return float_result(&[operand], result);
+        }
+    })
+}
+
+// Multiplies by the ratio of half turns, like pi / 180 from degrees to radians
+fn convert_angle(
+    value: Value,
+    from: AngleUnit,
+    to: AngleUnit,
+    context: &Context,
+) -> Result<Value, Error> {
+    if from == to {
+        return Ok(value);
+    }
+    if let Some(digits) = decimal_digits(context)
+        && let Some(d) = to_decimal(&value, digits)
+    {
+        return Ok(Value::Decimal(decimal::convert_angle(&d, from, to, digits)));
+    }
+    let half_turn = |unit| match unit {
+        AngleUnit::Radians => Value::Number(std::f64::consts::PI),
+        AngleUnit::Degrees => number(BigRational::from_integer(180.into()), context),
+        AngleUnit::Gradians => number(BigRational::from_integer(200.into()), context),
+    };
+    let scaled = apply_binary(&BinaryOperator::Multiply, value, half_turn(to), context)?;
+    apply_binary(&BinaryOperator::Divide, scaled, half_turn(from), context)
+}
+
+// Multiples of 15 degrees where a trig function is rational are exact, so cos 90 is 0 rather
+// than rounding noise. Only degrees and gradians can hit them exactly.
+fn exact_trig(
+    operation: &UnaryOperator,
+    operand: &Value,
+    angle: AngleUnit,
+    context: &Context,
+) -> Result<Option<Value>, Error> {
+    let Some(turns) = to_fraction(operand).and_then(|x| in_turns(x, angle)) else {
+        return Ok(None);
+    };
+    let steps = turns * BigInt::from(24);
+    if !steps.is_integer() {
+        return Ok(None);
+    }
+    // Position on the circle in steps of 15 degrees, starting at 0
+    let step = steps
+        .to_integer()
+        .mod_floor(&BigInt::from(24))
+        .to_u8()
+        .unwrap_or(0);
+    let sine = |step: u8| match step {
+        0 | 12 => Some((0, 1)),
+        2 | 10 => Some((1, 2)),
+        6 => Some((1, 1)),
+        14 | 22 => Some((-1, 2)),
+        18 => Some((-1, 1)),
+        _ => None,
+    };
+    let cosine = |step: u8| sine((step + 6) % 24); // cos x = sin(x + 90)
+    let tangent = |step: u8| match step % 12 {
+        0 => Some((0, 1)),
+        3 => Some((1, 1)),
+        6 => Some((1, 0)), // Undefined, like a division by zero
+        9 => Some((-1, 1)),
+        _ => None,
+    };
+    let (numerator, denominator) = match operation {
+        UnaryOperator::Sin => sine(step),
+        UnaryOperator::Cos => cosine(step),
+        UnaryOperator::Tan => tangent(step),
+        // The reciprocals swap numerator and denominator
+        UnaryOperator::Csc => sine(step).map(|(n, d)| (d, n)),
+        UnaryOperator::Sec => cosine(step).map(|(n, d)| (d, n)),
+        UnaryOperator::Cot => tangent(step).map(|(n, d)| (d, n)),
+        _ => None,
+    }
+    .unzip();
+    match (numerator, denominator) {
+        (Some(_), Some(0)) => Err(Error::Domain(format!(
+            "{} is undefined at {operand}",
+            trig_name(operation)
+        ))),
+        (Some(numerator), Some(denominator)) => Ok(Some(number(
+            BigRational::new(BigInt::from(numerator), BigInt::from(denominator)),
+            context,
+        ))),
+        _ => Ok(None),
+    }
+}
+
+// The inverses of exact_trig where both sides are rational, like asin(1/2) = 30 degrees
+fn exact_inverse_trig(
+    operation: &UnaryOperator,
+    operand: &Value,
+    angle: AngleUnit,
+    context: &Context,
+) -> Option<Value> {
+    let full_turn = match angle {
+        AngleUnit::Radians => return None,
+        AngleUnit::Degrees => 360,
+        AngleUnit::Gradians => 400,
+    };
+    // Only 0, ±1/2 and ±1 have rational inverses, so twice the operand is a small integer
+    let twice = to_fraction(operand)? * BigInt::from(2);
+    if !twice.is_integer() {
+        return None;
+    }
+    let twice = twice.to_integer().to_i32()?;
+    // Angle in steps of 15 degrees
+    let asin = match twice {
+        -2 => Some(-6),
+        -1 => Some(-2),
+        0 => Some(0),
+        1 => Some(2),
+        2 => Some(6),
+        _ => None,
+    };
+    let steps = match operation {
+        UnaryOperator::Asin => asin?,
+        UnaryOperator::Acos => 6 - asin?, // acos x = 90 - asin x
+        UnaryOperator::Atan if matches!(twice, -2 | 0 | 2) => 3 * twice / 2,
+        _ => return None,
+    };
+    let result = BigRational::new(BigInt::from(steps * full_turn), BigInt::from(24));
+    Some(number(result, context))
+}
+
+// An angle as a fraction of a full turn, where the unit makes that exact
+fn in_turns(angle: BigRational, unit: AngleUnit) -> Option<BigRational> {
+    match unit {
+        AngleUnit::Radians => None,
+        AngleUnit::Degrees => Some(angle / BigInt::from(360)),
+        AngleUnit::Gradians => Some(angle / BigInt::from(400)),
+    }
+}
+
+fn trig_name(operation: &UnaryOperator) -> &'static str {
+    match operation {
+        UnaryOperator::Sin => "sin",
+        UnaryOperator::Cos => "cos",
+        UnaryOperator::Tan => "tan",
+        UnaryOperator::Sec => "sec",
+        UnaryOperator::Csc => "csc",
+        UnaryOperator::Cot => "cot",
+        UnaryOperator::Asin => "asin",
+        UnaryOperator::Acos => "acos",
+        UnaryOperator::Acosh => "acosh",
+        UnaryOperator::Atanh => "atanh",
+        _ => "trig function",
+    }
+}
+
+// tan and sec are undefined at odd multiples of pi/2, csc and cot at multiples of pi. Rounding
+// keeps pi/2 from reaching the pole, so radians within rounding of one count as the pole.
+fn check_trig_pole(
+    operation: &UnaryOperator,
+    operand: &Value,
+    radians: &Value,
+    context: &Context,
+) -> Result<(), Error> {
+    let odd = match operation {
+        UnaryOperator::Tan | UnaryOperator::Sec => true,
+        UnaryOperator::Csc | UnaryOperator::Cot => false,
+        _ => return Ok(()),
+    };
+    let at_pole = match radians {
+        Value::Decimal(x) => decimal_digits(context)
+            .is_some_and(|digits| decimal::is_half_pi_multiple(x, odd, digits)),
+        Value::Number(x) => {
+            let k = (x / FRAC_PI_2).round();
+            k.abs() < 1e15
+                && (k % 2.0 != 0.0) == odd
+                && (x - k * FRAC_PI_2).abs() <= 4.0 * f64::EPSILON * (k * FRAC_PI_2).abs()
+        }
+        // Other multiples of pi/2 are irrational
+        _ => !odd && radians.is_zero(),
+    };
+    if at_pole {
+        return Err(Error::Domain(format!(
+            "{} is undefined at {operand}",
+            trig_name(operation)
+        )));
+    }
+    Ok(())
+}
+
+// Inverse trig and hyperbolic functions outside the real numbers they are defined for, unless
+// complex results are allowed. The poles of atanh at 1 and -1 are undefined either way.
+fn check_real_domain(
+    operation: &UnaryOperator,
+    operand: &Value,
+    context: &Context,
+) -> Result<(), Error> {
+    let Some(x) = to_fraction(operand) else {
+        return Ok(());
+    };
+    let one = BigRational::one();
+    let outside = match operation {
+        UnaryOperator::Atanh if x.abs() == one => true,
+        _ if complex_numbers(context) => false,
+        UnaryOperator::Asin | UnaryOperator::Acos => x.abs() > one,
+        UnaryOperator::Acosh => x < one,
+        UnaryOperator::Atanh => x.abs() >= one,
+        _ => false,
+    };
+    if outside {
+        return Err(Error::Domain(format!(
+            "{} is undefined at {operand}",
+            trig_name(operation)
+        )));
+    }
+    Ok(())
+}
+
+// Γ(x) and its relatives are undefined at zero and the negative integers
+fn check_pole(operation: &UnaryOperator, operand: &Value) -> Result<(), Error> {
+    let name = match operation {
+        UnaryOperator::Factorial => "factorial",
+        UnaryOperator::DoubleFactorial => "double factorial",
+        UnaryOperator::Gamma => "gamma",
+        UnaryOperator::LogGamma => "lgamma",
+        UnaryOperator::Digamma => "digamma",
+        _ => return Ok(()),
+    };
+    // x! = Γ(x + 1), so its poles start at -1, and x!! has Γ(x/2 + 1) at -2, -4, ...
+    let is_pole = |n: &BigInt| match operation {
+        UnaryOperator::Factorial => *n <= BigInt::from(-1),
+        UnaryOperator::DoubleFactorial => n.is_negative() && n.is_even(),
+        _ => *n <= BigInt::from(0),
+    };
+    match operand.to_integer() {
+        Some(n) if is_pole(&n) => Err(Error::Domain(format!("{name} is undefined at {operand}"))),
+        _ => Ok(()),
+    }
+}
+
+// Exact and decimal factorials beyond MAX_BITS are an error rather than a floating point
+// infinity. x!! is computed from x!, so it is refused where x! would be.
+fn check_size(operation: &UnaryOperator, operand: &Value) -> Result<(), Error> {
+    let shift = match operation {
+        UnaryOperator::Factorial | UnaryOperator::DoubleFactorial => 1.0,
+        UnaryOperator::Gamma => 0.0, // Γ(x) = (x - 1)!
+        _ => return Ok(()),
+    };
+    let x = match operand {
+        Value::Decimal(d) => d.to_f64(),
+        _ if let Some(x) = to_rational(operand) => x.to_f64().unwrap_or(f64::NAN),
+        _ => return Ok(()),
+    };
+    if x == f64::INFINITY || x > 0.0 && special::ln_gamma(x + shift) / LN_2 > MAX_BITS as f64 {
+        return Err(too_large());
+    }
+    Ok(())
+}
+
+// Like check_size, for exact and decimal powers. Decimals are refused close to zero as well, as
+// their exponent has the same range.
+fn check_power(base: &Value, exponent: &Value, context: &Context) -> Result<(), Error> {
+    let digits = decimal_digits(context);
+    let bits = if let Some(digits) = digits
+        && let (Some(base), Some(exponent)) =
+            (to_decimal(base, digits), to_decimal(exponent, digits))
+    {
+        if base.is_zero() {
+            return Ok(());
+        }
+        base.log10() / LOG10_2 * exponent.to_f64()
+    } else if let (Some(base), Some(exponent)) = (to_rational(base), to_rational(exponent)) {
+        if base.is_zero() {
+            return Ok(());
+        }
+        let exponent = exponent.to_f64().unwrap_or(f64::NAN);
+        (integer::log2(base.numer()) - integer::log2(base.denom())) * exponent
+    } else {
+        return Ok(());
+    };
+    if bits > MAX_BITS as f64 {
+        Err(too_large())
+    } else if digits.is_some() && bits < -(MAX_BITS as f64) {
+        Err(Error::Domain("result is too small".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+// Exact numbers beyond the range of f64 would turn into infinities in floating point, so they
+// are an error instead, and so is an infinite result computed from finite exact numbers
+fn float_result(operands: &[Value], result: f64) -> Result<Value, Error> {
+    let exact = |operand: &Value| !matches!(operand, Value::Number(_));
+    let overflow = operands
+        .iter()
+        .any(|operand| exact(operand) && !operand.to_f64().is_finite())
+        || result.is_infinite()
+            && operands.iter().any(exact)
+            && operands.iter().all(|operand| operand.to_f64().is_finite());
+    if overflow {
+        return Err(too_large());
+    }
+    Ok(Value::Number(result))
+}
+
+fn too_large() -> Error {
+    Error::Domain("result is too large".to_string())
+}
+
+// Lists apply the operator to each element, pairing up the elements of two lists
+fn broadcast_binary(
+    operation: &BinaryOperator,
+    left: Value,
+    right: Value,
+    context: &Context,
+) -> Result<Value, Error> {
+    match (left, right) {
+        (Value::List(left), Value::List(right)) => {
+            if left.len() != right.len() {
+                return Err(Error::LengthMismatch {
+                    left: left.len(),
+                    right: right.len(),
+                });
+            }
+            left.into_iter()
+                .zip(right)
+                .map(|(l, r)| broadcast_binary(operation, l, r, context))
+                .collect::<Result<_, _>>()
+                .map(Value::List)
+        }
+        (Value::List(left), right) => left
+            .into_iter()
+            .map(|l| broadcast_binary(operation, l, right.clone(), context))
+            .collect::<Result<_, _>>()
+            .map(Value::List),
+        (left, Value::List(right)) => right
+            .into_iter()
+            .map(|r| broadcast_binary(operation, left.clone(), r, context))
+            .collect::<Result<_, _>>()
+            .map(Value::List),
+        // solve only short-circuits whole operands, so elements are checked here. Past these
+        // arms && and || only depend on the right side, which is all the kernels look at.
+        (left, _) if matches!(operation, BinaryOperator::And) && left.is_zero() => {
+            Ok(truth(false, context))
+        }
+        (left, _) if matches!(operation, BinaryOperator::Or) && !left.is_zero() => {
+            Ok(truth(true, context))
+        }
+        (left, right) => {
+            if matches!(operation, BinaryOperator::Power) {
+                check_power(&left, &right, context)?;
+            }
+            apply_binary(operation, left, right, context)
+        }
+    }
+}
+
+fn apply_binary(
+    operation: &BinaryOperator,
+    left: Value,
+    right: Value,
+    context: &Context,
+) -> Result<Value, Error> {
+    let (left, right) = (left.expand(), right.expand());
+    Ok(match (&left, &right) {
+        _ if let Some(digits) = decimal_digits(context)
+            && let (Some(l), Some(r)) = (to_decimal(&left, digits), to_decimal(&right, digits))
+            && let Some(result) = decimal::binary(operation, &l, &r, digits) =>
+        {
+            Value::Decimal(result)
+        }
+        (Value::Integer(l), Value::Integer(r))
+            if let Some(exact) = integer::binary(operation, l, r) =>
+        {
+            Value::Integer(exact)
+        }
+        // Fractions combine exactly with whole numbers too
+        (Value::Rational(_), _) | (_, Value::Rational(_))
+            if let (Some(l), Some(r)) = (to_rational(&left), to_rational(&right))
+                && let Some(exact) = rational::binary(operation, &l, &r) =>
+        {
+            Value::Rational(exact)
+        }
+        (Value::Complex(_), _) | (_, Value::Complex(_)) => complex::value(complex::binary(
+            operation,
+            left.to_complex(),
+            right.to_complex(),
+        )?),
+        _ if complex_numbers(context)
+            && complex::leaves_reals_binary(operation, left.to_f64(), right.to_f64()) =>
+        {
+            complex::value(complex::binary(
+                operation,
+                left.to_complex(),
+                right.to_complex(),
+            )?)
+        }
+        _ => {
+            let result = binary(operation, left.to_f64(), right.to_f64())?;
+            return float_result(&[left, right], result);
+        }
+    })
+}
+
+// max and min: decimals and exact numbers compare exactly, anything else as floating point
+fn extremum(function: &Function, arguments: Vec<Value>, context: &Context) -> Result<Value, Error> {
+    let largest = matches!(function, Function::Max);
+    let arguments: Vec<Value> = flatten(arguments).into_iter().map(Value::expand).collect();
+    if arguments.is_empty() {
+        return Err(math_error("extremum of an empty list encountered"));
+    }
+    if arguments
+        .iter()
+        .any(|argument| matches!(argument, Value::Complex(_)))
+    {
+        return Err(math_error("complex numbers cannot be compared"));
+    }
+    // NaN stays NaN, f64::max and f64::min would skip it
+    if arguments
+        .iter()
+        .any(|argument| matches!(argument, Value::Number(n) if n.is_nan()))
+    {
+        return Ok(Value::Number(f64::NAN));
+    }
+    let exact: Option<Vec<BigRational>> = arguments.iter().map(to_rational).collect();
+
+    if let Some(digits) = decimal_digits(context)
+        && let Some(decimals) = to_decimals(&arguments, digits)
+    {
+        return Ok(Value::Decimal(extreme(decimals, largest)));
+    }
+    if let Some(exact) = exact {
+        // Whole numbers stay whole, fractions stay fractions
+        let result = extreme(exact, largest);
+        if arguments
+            .iter()
+            .all(|argument| matches!(argument, Value::Integer(_)))
+        {
+            return Ok(Value::Integer(result.to_integer()));
+        }
+        return Ok(Value::Rational(result));
+    }
+    let reals = arguments.iter().map(Value::to_f64);
+    Ok(Value::Number(if largest {
+        reals.fold(f64::NEG_INFINITY, f64::max)
+    } else {
+        reals.fold(f64::INFINITY, f64::min)
+    }))
+}
+
+// Functions with an argument list other than if, max and min
+fn call(function: &Function, arguments: Vec<Value>, context: &Context) -> Result<Value, Error> {
+    if function.takes_list() {
+        return call_with_list(function, arguments, context);
+    }
+    if function.takes_matrix() {
+        return call_with_matrix(function, arguments, context);
+    }
+    // Functions of any number of arguments take the elements of lists as arguments,
+    // the others are applied to each element
+    let arguments = if matches!(
+        function,
+        Function::Gcd | Function::Lcm | Function::Multinomial
+    ) {
+        flatten(arguments)
+    } else if arguments
+        .iter()
+        .any(|argument| matches!(argument, Value::List(_)))
+    {
+        return broadcast_call(function, arguments, context);
+    } else {
+        arguments
+    };
+    if function.takes_integers() {
+        return number_theory::call(function, &arguments);
+    }
+    let arguments = arguments.into_iter().map(Value::expand).collect();
+    let result = call_in_radians(function, arguments, context)?;
+    match function {
+        Function::Atan2 => {
+            convert_angle(result, AngleUnit::Radians, context.settings.angle, context)
+        }
+        _ => Ok(result),
+    }
+}
+
+fn call_in_radians(
+    function: &Function,
+    arguments: Vec<Value>,
+    context: &Context,
+) -> Result<Value, Error> {
+    if matches!(function, Function::Beta) {
+        for argument in &arguments {
+            check_pole(&UnaryOperator::Gamma, argument)
+                .map_err(|_| Error::Domain(format!("beta is undefined at {argument}")))?;
+        }
+    }
+    if let Some(digits) = decimal_digits(context)
+        && let Some(decimals) = to_decimals(&arguments, digits)
+        && let Some(result) = decimal::call(function, &decimals, digits)
+    {
+        return Ok(Value::Decimal(result));
+    }
+
+    let reals: Vec<f64> = arguments.iter().map(Value::to_f64).collect();
+    if arguments
+        .iter()
+        .any(|argument| matches!(argument, Value::Complex(_)))
+        || complex_numbers(context) && complex::leaves_reals_call(function, &reals)
+    {
+        let arguments: Vec<Complex64> = arguments.iter().map(Value::to_complex).collect();
+        return Ok(complex::value(complex::call(function, &arguments)?));
+    }
+
+    let result = match function {
+        Function::Log => logarithm(reals[0], reals.get(1).copied().unwrap_or(10.0))?,
+        Function::Atan2 => reals[0].atan2(reals[1]), // Angle of (x, y) in radians
+        Function::Hypot => reals[0].hypot(reals[1]), // sqrt(x^2 + y^2)
+        Function::Beta => special::beta(reals[0], reals[1]), // Γ(a) Γ(b) / Γ(a + b)
+        // Unary functions are always parsed into Token::Unary, the rest are handled by solve
+        // and call
+        _ => unreachable!(),
+    };
+    float_result(&arguments, result)
+}
+
+// Calls the function once per element, with the matching element of every list argument
+// and the other arguments unchanged
+fn broadcast_call(
+    function: &Function,
+    arguments: Vec<Value>,
+    context: &Context,
+) -> Result<Value, Error> {
+    let mut length = None;
+    for argument in &arguments {
+        if let Value::List(elements) = argument {
+            match length {
+                Some(length) if length != elements.len() => {
+                    return Err(Error::LengthMismatch {
+                        left: length,
+                        right: elements.len(),
+                    });
                 }
-                UnaryOperator::SquareRoot => {
-                    // Square root: sqrt(x) = x^(1/2), for non-negative numbers
-                    if operand < 0.0 {
-                        log(Level::Warning, "Square root of negative number encountered");
-                        f64::NAN
-                    } else {
-                        operand.sqrt()
-                    }
+                _ => length = Some(elements.len()),
+            }
+        }
+    }
+
+    let results = (0..length.unwrap_or(0)).map(|i| {
+        let arguments = arguments
+            .iter()
+            .map(|argument| match argument {
+                Value::List(elements) => elements[i].clone(),
+                other => other.clone(),
+            })
+            .collect();
+        call(function, arguments, context)
+    });
+    results.collect::<Result<_, _>>().map(Value::List)
+}
+
+// length, concat, sort, sum, prod and statistics
+fn call_with_list(
+    function: &Function,
+    arguments: Vec<Value>,
+    context: &Context,
+) -> Result<Value, Error> {
+    // Lists are joined, other values become single elements
+    if matches!(function, Function::Concat) {
+        let mut result = Vec::new();
+        for argument in arguments {
+            match argument {
+                Value::List(elements) => result.extend(elements),
+                other => result.push(other),
+            }
+        }
+        return Ok(Value::List(result));
+    }
+
+    let name = match function {
+        Function::Length => "length",
+        Function::Sort => "sort",
+        Function::Sum => "sum",
+        Function::Product => "prod",
+        Function::Mean => "mean",
+        Function::Median => "median",
+        Function::Mode => "mode",
+        Function::Variance => "var",
+        Function::PopulationVariance => "varp",
+        Function::Deviation => "stdev",
+        Function::PopulationDeviation => "stdevp",
+        Function::Range => "range",
+        Function::Percentile => "percentile",
+        Function::Quartiles => "quartiles",
+        Function::ZScore => "zscore",
+        _ => unreachable!(),
+    };
+    // zscore(x, list) standardizes x against the list, the others take the list first
+    let mut arguments = arguments.into_iter();
+    let target = if matches!(function, Function::ZScore) && arguments.len() == 2 {
+        arguments.next()
+    } else {
+        None
+    };
+    let elements = match arguments.next() {
+        Some(Value::List(elements)) => elements,
+        Some(found) => {
+            return Err(Error::ListRequired {
+                name: name.to_string(),
+                found: found.to_string(),
+            });
+        }
+        None => unreachable!(),
+    };
+    match function {
+        Function::Length => Ok(Value::Integer(elements.len().into())),
+        Function::Sort => sort(elements),
+        // Lists of lists add up element by element
+        Function::Sum => elements
+            .into_iter()
+            .try_fold(Value::Integer(0.into()), |total, element| {
+                broadcast_binary(&BinaryOperator::Add, total, element, context)
+            }),
+        Function::Product => elements
+            .into_iter()
+            .try_fold(Value::Integer(1.into()), |total, element| {
+                broadcast_binary(&BinaryOperator::Multiply, total, element, context)
+            }),
+        Function::ZScore => {
+            let mean = statistic(&Function::Mean, name, &elements, None, context)?;
+            let deviation = statistic(&Function::Deviation, name, &elements, None, context)?;
+            let target = target.unwrap_or(Value::List(elements));
+            let difference = broadcast_binary(&BinaryOperator::Subtract, target, mean, context)?;
+            broadcast_binary(&BinaryOperator::Divide, difference, deviation, context)
+        }
+        _ => statistic(function, name, &elements, arguments.next(), context),
+    }
+}
+
+// Statistics of the real numbers in a list, including those in nested lists
+fn statistic(
+    function: &Function,
+    name: &str,
+    elements: &[Value],
+    argument: Option<Value>,
+    context: &Context,
+) -> Result<Value, Error> {
+    let Some(mut values) = flatten(elements.to_vec())
+        .iter()
+        .map(to_fraction)
+        .collect::<Option<Vec<BigRational>>>()
+    else {
+        return Err(math_error("statistics need finite real numbers"));
+    };
+    // A sample variance divides by n - 1
+    let least = match function {
+        Function::Variance | Function::Deviation => 2,
+        _ => 1,
+    };
+    if values.len() < least {
+        let noun = if least == 1 { "value" } else { "values" };
+        return Err(Error::Domain(format!(
+            "{name} needs at least {least} {noun}"
+        )));
+    }
+    values.sort();
+
+    match function {
+        Function::Mean => Ok(number(statistics::mean(&values), context)),
+        Function::Median => {
+            let half = BigRational::from_integer(50.into());
+            Ok(number(statistics::percentile(&values, &half), context))
+        }
+        Function::Mode => Ok(number(statistics::mode(&values), context)),
+        Function::Variance | Function::PopulationVariance => {
+            let sample = matches!(function, Function::Variance);
+            Ok(number(statistics::variance(&values, sample), context))
+        }
+        Function::Deviation | Function::PopulationDeviation => {
+            let sample = matches!(function, Function::Deviation);
+            let variance = number(statistics::variance(&values, sample), context);
+            unary_value(&UnaryOperator::SquareRoot, variance, context)
+        }
+        Function::Range => {
+            let range = &values[values.len() - 1] - &values[0];
+            Ok(number(range, context))
+        }
+        Function::Percentile => {
+            let argument = argument.unwrap();
+            let p = match to_fraction(&argument) {
+                Some(p)
+                    if p >= BigRational::zero() && p <= BigRational::from_integer(100.into()) =>
+                {
+                    p
                 }
-                UnaryOperator::Sin => operand.sin(), // Sine function in radians
-                UnaryOperator::Cos => operand.cos(), // Cosine function in radians
-                UnaryOperator::Tan => operand.tan(), // Tangent function in radians
-                UnaryOperator::Ln => {
-                    // Natural logarithm: ln(x), for positive numbers
-                    if operand <= 0.0 {
-                        log(
-                            Level::Warning,
-                            "Natural logarithm of non-positive number encountered",
-                        );
-                        f64::NAN
-                    } else {
-                        operand.ln()
-                    }
+                _ => {
+                    return Err(Error::Domain(format!(
+                        "percentile must be between 0 and 100, got {argument}"
+                    )));
                 }
-                UnaryOperator::Floor => operand.floor(), // Floor: largest integer <= x
-                UnaryOperator::Ceil => operand.ceil(),   // Ceiling: smallest integer >= x
-                UnaryOperator::Abs => operand.abs(),     // Absolute value: |x|
-                UnaryOperator::Round => operand.round(), // Round to nearest integer
-                UnaryOperator::Negate => -operand,       // Unary minus: -x
             };
+            Ok(number(statistics::percentile(&values, &p), context))
+        }
+        Function::Quartiles => Ok(Value::List(
+            [25, 50, 75]
+                .into_iter()
+                .map(|p| {
+                    let p = BigRational::from_integer(p.into());
+                    number(statistics::percentile(&values, &p), context)
+                })
+                .collect(),
+        )),
+        _ => unreachable!(),
+    }
+}
 
-            result
-        }
-        Token::Binary(t) => {
-            let left = solve(*t.left);
-            let right = solve(*t.right);
-
-            let result = match t.operation {
-                BinaryOperator::Add => left + right,
-                BinaryOperator::Subtract => left - right,
-                BinaryOperator::Multiply => left * right,
-                BinaryOperator::Divide => match right {
-                    0.0 => {
-                        log(Level::Warning, "Division by zero encountered");
-                        f64::NAN
-                    }
-                    _ => left / right,
-                },
-                BinaryOperator::Power => left.powf(right),
-                BinaryOperator::Modulo => left % right,
-                BinaryOperator::Log => {
-                    // Logarithm: log_base(right) of left, with domain checks
-                    if left <= 0.0 || right <= 0.0 || right == 1.0 {
-                        log(Level::Warning, "Invalid logarithm base or argument");
-                        f64::NAN
-                    } else {
-                        left.log(right)
-                    }
+// Ascending, comparing exactly where possible so huge integers and close decimals stay in order
+fn sort(mut elements: Vec<Value>) -> Result<Value, Error> {
+    if elements
+        .iter()
+        .any(|element| matches!(element, Value::Complex(_) | Value::List(_)))
+    {
+        return Err(math_error("only real numbers can be sorted"));
+    }
+    elements.sort_by(|a, b| match (to_fraction(a), to_fraction(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => a.to_f64().total_cmp(&b.to_f64()),
+    });
+    Ok(Value::List(elements))
+}
+
+// det, inv, transpose, rank, trace, identity and linsolve
+fn call_with_matrix(
+    function: &Function,
+    arguments: Vec<Value>,
+    context: &Context,
+) -> Result<Value, Error> {
+    let name = match function {
+        Function::Determinant => "det",
+        Function::Inverse => "inv",
+        Function::Transpose => "transpose",
+        Function::Rank => "rank",
+        Function::Trace => "trace",
+        Function::Identity => "identity",
+        Function::LinearSolve => "linsolve",
+        _ => unreachable!(),
+    };
+    let mut arguments = arguments.into_iter();
+    let argument = arguments.next().unwrap();
+
+    match function {
+        Function::Identity => {
+            let n = match argument.to_integer().map(|n| n.to_usize()) {
+                Some(Some(n)) if (1..=MAX_MATRIX_SIZE).contains(&n) => n,
+                Some(_) => {
+                    return Err(Error::Domain(format!(
+                        "identity needs a size from 1 to {MAX_MATRIX_SIZE}, got {argument}"
+                    )));
+                }
+                None => {
+                    return Err(Error::IntegerRequired {
+                        name: name.to_string(),
+                        found: argument.to_string(),
+                    });
                 }
             };
+            Ok(from_fractions(matrix::identity(n), context))
+        }
+        Function::Transpose => {
+            let rows = matrix_rows(name, argument)?;
+            let columns = (0..rows[0].len())
+                .map(|j| Value::List(rows.iter().map(|row| row[j].clone()).collect()))
+                .collect();
+            Ok(Value::List(columns))
+        }
+        Function::Rank => {
+            let rows = matrix_rows(name, argument)?;
+            let rows = fractions(&rows)?;
+            Ok(Value::Integer(matrix::rank(&rows).into()))
+        }
+        Function::Trace => {
+            let rows = square_rows(name, argument)?;
+            let mut diagonal = rows.into_iter().enumerate().map(|(i, row)| row[i].clone());
+            let first = diagonal.next().unwrap();
+            diagonal.try_fold(first, |total, element| {
+                apply_binary(&BinaryOperator::Add, total, element, context)
+            })
+        }
+        Function::Determinant => {
+            let rows = square_rows(name, argument)?;
+            let rows = fractions(&rows)?;
+            Ok(number(matrix::determinant(&rows), context))
+        }
+        Function::Inverse => {
+            let rows = square_rows(name, argument)?;
+            let rows = fractions(&rows)?;
+            match matrix::inverse(&rows) {
+                Some(inverse) => Ok(from_fractions(inverse, context)),
+                None => Err(Error::Domain("inv of a singular matrix".to_string())),
+            }
+        }
+        Function::LinearSolve => {
+            let a = square_rows(name, argument)?;
+            // A list of numbers is a single right-hand side, and gives a list back
+            let b = arguments.next().unwrap();
+            let vector = is_vector(&b);
+            let b = if let (true, Value::List(elements)) = (vector, &b) {
+                elements.iter().map(|e| vec![e.clone()]).collect()
+            } else {
+                matrix_rows(name, b)?
+            };
+            if a.len() != b.len() {
+                return Err(Error::DimensionMismatch {
+                    operation: name.to_string(),
+                    left: dimensions(&a),
+                    right: dimensions(&b),
+                });
+            }
+            let (a, b) = (fractions(&a)?, fractions(&b)?);
+            let Some(solution) = matrix::solve(&a, &b) else {
+                return Err(Error::Domain("linsolve of a singular matrix".to_string()));
+            };
+            if vector {
+                let column = solution.into_iter().flatten();
+                Ok(Value::List(column.map(|x| number(x, context)).collect()))
+            } else {
+                Ok(from_fractions(solution, context))
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Matrix multiplication with @. A list of numbers is a row on the left and a column on the
+// right, and that dimension is dropped from the result, so two lists give their dot product.
+fn matrix_product(left: Value, right: Value, context: &Context) -> Result<Value, Error> {
+    let (row, column) = (is_vector(&left), is_vector(&right));
+    let a = match left {
+        Value::List(elements) if row => vec![elements],
+        left => matrix_rows("@", left)?,
+    };
+    let b = match right {
+        Value::List(elements) if column => elements.into_iter().map(|e| vec![e]).collect(),
+        right => matrix_rows("@", right)?,
+    };
+    if a[0].len() != b.len() {
+        return Err(Error::DimensionMismatch {
+            operation: "@".to_string(),
+            left: dimensions(&a),
+            right: dimensions(&b),
+        });
+    }
 
-            result
+    let entry = |i: usize, j: usize| {
+        let mut products = (0..b.len()).map(|k| {
+            apply_binary(
+                &BinaryOperator::Multiply,
+                a[i][k].clone(),
+                b[k][j].clone(),
+                context,
+            )
+        });
+        let first = products.next().unwrap()?;
+        products.try_fold(first, |total, product| {
+            apply_binary(&BinaryOperator::Add, total, product?, context)
+        })
+    };
+    let rows: Vec<Vec<Value>> = (0..a.len())
+        .map(|i| (0..b[0].len()).map(|j| entry(i, j)).collect())
+        .collect::<Result<_, _>>()?;
+
+    Ok(match (row, column) {
+        (true, true) => rows[0][0].clone(),
+        (true, false) => Value::List(rows.into_iter().next().unwrap()),
+        (false, true) => Value::List(rows.into_iter().flatten().collect()),
+        (false, false) => Value::List(rows.into_iter().map(Value::List).collect()),
+    })
+}
+
+// The rows of a matrix, a non-empty list of lists of numbers that all have the same length
+fn matrix_rows(name: &str, value: Value) -> Result<Vec<Vec<Value>>, Error> {
+    let invalid = |value: &Value| Error::MatrixRequired {
+        name: name.to_string(),
+        found: value.to_string(),
+    };
+    let Value::List(rows) = &value else {
+        return Err(invalid(&value));
+    };
+    let rows: Option<Vec<Vec<Value>>> = rows
+        .iter()
+        .map(|row| match row {
+            Value::List(elements) if is_vector(row) => Some(elements.clone()),
+            _ => None,
+        })
+        .collect();
+    match rows {
+        Some(rows) if !rows.is_empty() && rows.iter().all(|row| row.len() == rows[0].len()) => {
+            Ok(rows)
         }
-        Token::Value(n) => return n, // Literal number value
-        Token::Constant(c) => {
-            // Mathematical constants
-            match c {
-                Constant::Pi => std::f64::consts::PI,
-                Constant::E => std::f64::consts::E,
+        _ => Err(invalid(&value)),
+    }
+}
+
+fn square_rows(name: &str, value: Value) -> Result<Vec<Vec<Value>>, Error> {
+    let rows = matrix_rows(name, value)?;
+    if rows.len() != rows[0].len() {
+        return Err(Error::SquareRequired {
+            name: name.to_string(),
+            found: dimensions(&rows),
+        });
+    }
+    Ok(rows)
+}
+
+// A non-empty list without nested lists
+fn is_vector(value: &Value) -> bool {
+    match value {
+        Value::List(elements) => {
+            !elements.is_empty() && !elements.iter().any(|e| matches!(e, Value::List(_)))
+        }
+        _ => false,
+    }
+}
+
+// Rows x columns, like 2x3
+fn dimensions(rows: &[Vec<Value>]) -> String {
+    format!("{}x{}", rows.len(), rows[0].len())
+}
+
+// The exact entries of a matrix, which must be finite real numbers
+fn fractions(rows: &[Vec<Value>]) -> Result<Vec<Vec<BigRational>>, Error> {
+    rows.iter()
+        .map(|row| row.iter().map(to_fraction).collect())
+        .collect::<Option<_>>()
+        .ok_or_else(|| math_error("matrices need finite real numbers"))
+}
+
+fn from_fractions(rows: Vec<Vec<BigRational>>, context: &Context) -> Value {
+    Value::List(
+        rows.into_iter()
+            .map(|row| Value::List(row.into_iter().map(|x| number(x, context)).collect()))
+            .collect(),
+    )
+}
+
+// sum(k, from, to, body) and prod(k, from, to, body), evaluating body for each whole number k
+// from `from` to `to`. k shadows variables and parameters of the same name, and body sees the
+// others like the expression around it does.
+fn series(t: &SeriesToken, context: &mut Context) -> Result<Value, Error> {
+    let name = if matches!(t.operation, BinaryOperator::Add) {
+        "sum"
+    } else {
+        "prod"
+    };
+    let mut bounds = [&t.from, &t.to].into_iter().map(|bound| {
+        let value = solve(bound, context)?;
+        value.to_integer().ok_or_else(|| Error::IntegerRequired {
+            name: name.to_string(),
+            found: value.to_string(),
+        })
+    });
+    let (from, to) = (bounds.next().unwrap()?, bounds.next().unwrap()?);
+    if &to - &from >= BigInt::from(MAX_SERIES_TERMS - context.series_terms) {
+        return Err(Error::IterationLimit);
+    }
+
+    let frame = context.frames.last().cloned().unwrap_or_default();
+    context.frames.push(frame);
+    let result = accumulate(t, from, to, context);
+    context.frames.pop();
+    result
+}
+
+fn accumulate(
+    t: &SeriesToken,
+    from: BigInt,
+    to: BigInt,
+    context: &mut Context,
+) -> Result<Value, Error> {
+    // An empty range gives 0 for a sum and 1 for a product
+    let identity = if matches!(t.operation, BinaryOperator::Add) {
+        0
+    } else {
+        1
+    };
+    let mut total = number(BigRational::from_integer(identity.into()), context);
+    let mut k = from;
+    while k <= to {
+        context.series_terms += 1;
+        if context.series_terms > MAX_SERIES_TERMS {
+            return Err(Error::IterationLimit);
+        }
+        let index = number(BigRational::from_integer(k.clone()), context);
+        context
+            .frames
+            .last_mut()
+            .unwrap()
+            .insert(t.variable.clone(), index);
+        let term = solve(&t.body, context)?;
+        total = broadcast_binary(&t.operation, total, term, context)?;
+        k += 1;
+    }
+    Ok(total)
+}
+
+// xs[1] is the first element and xs[-1] the last
+fn element(token: &IndexToken, context: &mut Context) -> Result<Value, Error> {
+    let list = solve(&token.list, context)?;
+    let index = solve(&token.index, context)?;
+    let Value::List(elements) = list else {
+        return Err(Error::ListRequired {
+            name: "index".to_string(),
+            found: list.to_string(),
+        });
+    };
+    let Some(position) = index.to_integer() else {
+        return Err(Error::IntegerRequired {
+            name: "index".to_string(),
+            found: index.to_string(),
+        });
+    };
+
+    let length = elements.len();
+    let offset = if position.is_negative() {
+        &position + length
+    } else {
+        &position - 1
+    };
+    match offset.to_usize() {
+        Some(offset) if offset < length => Ok(elements.into_iter().nth(offset).unwrap()),
+        _ => Err(Error::IndexOutOfRange {
+            index: position.to_string(),
+            length,
+        }),
+    }
+}
+
+// Elements of lists, including nested ones, in order
+fn flatten(values: Vec<Value>) -> Vec<Value> {
+    let mut result = Vec::new();
+    for value in values {
+        match value {
+            Value::List(elements) => result.extend(flatten(elements)),
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+// Floating point version of the unary operators
+fn unary(operation: &UnaryOperator, operand: f64) -> Result<f64, Error> {
+    Ok(match operation {
+        UnaryOperator::Factorial => {
+            // Factorial: n! = n * (n-1) * ... * 1, and x! = Γ(x + 1) between the integers
+            if operand.fract() != 0.0 {
+                special::gamma(operand + 1.0)
+            } else if operand > 170.0 {
+                f64::INFINITY // 171! is beyond the largest f64
+            } else {
+                // Compute factorial using product of range
+                (1..=operand as u64).map(|k| k as f64).product()
             }
         }
-        Token::LastResult => {
-            // Retrieve the last computed result from thread-local storage
-            LAST_RESULT.with(|last_result| match *last_result.borrow() {
-                Some(value) => value,
-                None => {
-                    log(Level::Warning, "No last result available");
-                    f64::NAN
-                }
-            })
+        UnaryOperator::DoubleFactorial => special::double_factorial(operand),
+        UnaryOperator::SquareRoot => {
+            // Square root: sqrt(x) = x^(1/2), for non-negative numbers
+            if operand < 0.0 {
+                return Err(math_error("square root of negative number encountered"));
+            }
+            operand.sqrt()
+        }
+        UnaryOperator::Sin => operand.sin(), // Sine function in radians
+        UnaryOperator::Cos => operand.cos(), // Cosine function in radians
+        UnaryOperator::Tan => operand.tan(), // Tangent function in radians
+        UnaryOperator::Sec => 1.0 / operand.cos(), // Secant: 1 / cos(x)
+        UnaryOperator::Csc => 1.0 / operand.sin(), // Cosecant: 1 / sin(x)
+        UnaryOperator::Cot => operand.cos() / operand.sin(), // Cotangent: cos(x) / sin(x)
+        // The domains of the inverse functions are checked by check_real_domain
+        UnaryOperator::Asin => operand.asin(), // Inverse sine in radians
+        UnaryOperator::Acos => operand.acos(), // Inverse cosine in radians
+        UnaryOperator::Atan => operand.atan(), // Inverse tangent in radians
+        UnaryOperator::Sinh => operand.sinh(), // Hyperbolic sine
+        UnaryOperator::Cosh => operand.cosh(), // Hyperbolic cosine
+        UnaryOperator::Tanh => operand.tanh(), // Hyperbolic tangent
+        UnaryOperator::Asinh => operand.asinh(), // Inverse hyperbolic sine
+        UnaryOperator::Acosh => operand.acosh(), // Inverse hyperbolic cosine
+        UnaryOperator::Atanh => operand.atanh(), // Inverse hyperbolic tangent
+        UnaryOperator::Ln => {
+            // Natural logarithm: ln(x), for positive numbers
+            if operand <= 0.0 {
+                return Err(math_error(
+                    "natural logarithm of non-positive number encountered",
+                ));
+            }
+            operand.ln()
+        }
+        UnaryOperator::Floor => operand.floor(), // Floor: largest integer <= x
+        UnaryOperator::Ceil => operand.ceil(),   // Ceiling: smallest integer >= x
+        UnaryOperator::Abs => operand.abs(),     // Absolute value: |x|
+        UnaryOperator::Round => operand.round(), // Round to nearest integer
+        UnaryOperator::Negate => -operand,       // Unary minus: -x
+        UnaryOperator::Not if operand.is_nan() => f64::NAN,
+        UnaryOperator::Not => (operand == 0.0) as u8 as f64, // Logical not: 1 if x is 0
+        UnaryOperator::Gamma => special::gamma(operand),     // Gamma function: Γ(n) = (n - 1)!
+        UnaryOperator::LogGamma => special::ln_gamma(operand), // ln |Γ(x)|
+        UnaryOperator::Digamma => special::digamma(operand), // ψ(x) = Γ'(x) / Γ(x)
+        UnaryOperator::Real | UnaryOperator::Conjugate => operand, // Real numbers are their own
+        UnaryOperator::Imaginary => 0.0,
+        UnaryOperator::Argument => 0.0f64.atan2(operand), // pi for negative numbers, else 0
+        // Angle units are converted by apply_unary, which knows the angle setting
+        UnaryOperator::Angle(_) => unreachable!(),
+    })
+}
+
+// Floating point version of the binary operators
+fn binary(operation: &BinaryOperator, left: f64, right: f64) -> Result<f64, Error> {
+    let truth = |condition: bool| condition as u8 as f64;
+    Ok(match operation {
+        BinaryOperator::Add => left + right,
+        BinaryOperator::Subtract => left - right,
+        BinaryOperator::Multiply => left * right,
+        BinaryOperator::Divide => match right {
+            0.0 => return Err(math_error("division by zero encountered")),
+            _ => left / right,
+        },
+        BinaryOperator::Power if left < 0.0 && right.is_finite() && right.fract() != 0.0 => {
+            return Err(math_error(
+                "fractional power of negative number encountered",
+            ));
         }
+        BinaryOperator::Power => left.powf(right),
+        BinaryOperator::Modulo => left % right,
+        BinaryOperator::Log => logarithm(left, right)?,
+        BinaryOperator::Equal => truth(left == right),
+        BinaryOperator::NotEqual => truth(left != right),
+        BinaryOperator::Less => truth(left < right),
+        BinaryOperator::LessEqual => truth(left <= right),
+        BinaryOperator::Greater => truth(left > right),
+        BinaryOperator::GreaterEqual => truth(left >= right),
+        BinaryOperator::And | BinaryOperator::Or => truth(right != 0.0),
+        // Matrices are multiplied by solve_binary, before reaching the elements
+        BinaryOperator::MatrixMultiply => unreachable!(),
+    })
+}
+
+fn solve_all(tokens: &[Token], context: &mut Context) -> Result<Vec<Value>, Error> {
+    tokens.iter().map(|token| solve(token, context)).collect()
+}
+
+// Logarithm: log_base(value), with domain checks
+fn logarithm(value: f64, base: f64) -> Result<f64, Error> {
+    if value <= 0.0 || base <= 0.0 || base == 1.0 {
+        Err(math_error("invalid logarithm base or argument"))
+    } else if base == 10.0 {
+        Ok(value.log10()) // exact for powers of ten, unlike the generic log
+    } else {
+        Ok(value.log(base))
+    }
+}
+
+// Fractions are only kept in rational mode, whole numbers are exact except in decimal mode
+fn number(value: BigRational, context: &Context) -> Value {
+    match context.settings.mode {
+        Mode::Rational => Value::Rational(value),
+        Mode::Decimal { digits } => Value::Decimal(Decimal::from_rational(&value, digits)),
+        Mode::Float if value.is_integer() => Value::Integer(value.to_integer()),
+        Mode::Float => Value::Number(Value::Rational(value).to_f64()),
+    }
+}
+
+// The exact value of any real number, including floats and decimals
+fn to_fraction(value: &Value) -> Option<BigRational> {
+    match value {
+        Value::Number(n) => BigRational::from_float(*n),
+        Value::Decimal(d) => d.to_rational(),
+        Value::Complex(_) | Value::List(_) => None,
+        _ => to_rational(value),
     }
 }
+
+fn to_rational(value: &Value) -> Option<BigRational> {
+    match value {
+        Value::Integer(n) => Some(BigRational::from_integer(n.clone())),
+        Value::Rational(r) => Some(r.clone()),
+        Value::Factors(factors) => Some(BigRational::from_integer(factors.value())),
+        Value::Number(_) | Value::Decimal(_) | Value::Complex(_) | Value::List(_) => None,
+    }
+}
+
+// Significant digits to evaluate with, in decimal mode
+fn decimal_digits(context: &Context) -> Option<usize> {
+    match context.settings.mode {
+        Mode::Decimal { digits } => Some(digits),
+        _ => None,
+    }
+}
+
+// Values from other modes (like variables assigned before switching) are converted,
+// except NaN and infinity
+fn to_decimal(value: &Value, digits: usize) -> Option<Decimal> {
+    match value {
+        Value::Number(n) => Decimal::from_f64(*n),
+        Value::Integer(n) => Some(Decimal::from_integer(n.clone())),
+        Value::Rational(r) => Some(Decimal::from_rational(r, digits)),
+        Value::Decimal(d) => Some(d.clone()),
+        Value::Factors(factors) => Some(Decimal::from_integer(factors.value())),
+        Value::Complex(_) | Value::List(_) => None,
+    }
+}
+
+fn to_decimals(values: &[Value], digits: usize) -> Option<Vec<Decimal>> {
+    values
+        .iter()
+        .map(|value| to_decimal(value, digits))
+        .collect()
+}
+
+// The largest or smallest of a non-empty list
+fn extreme<T: Ord>(values: Vec<T>, largest: bool) -> T {
+    let values = values.into_iter();
+    if largest { values.max() } else { values.min() }.unwrap()
+}
+
+fn complex_numbers(context: &Context) -> bool {
+    context.settings.complex.is_some()
+}
+
+// i is a name like any other unless complex numbers are enabled
+fn imaginary_unit(context: &Context) -> Result<Value, Error> {
+    if complex_numbers(context) {
+        Ok(Value::Complex(Complex64::I))
+    } else {
+        Err(Error::Domain(
+            "i needs complex numbers to be enabled".to_string(),
+        ))
+    }
+}
+
+// Booleans are represented as 1 (true) and 0 (false)
+fn truth(condition: bool, context: &Context) -> Value {
+    number(rational::truth(condition), context)
+}
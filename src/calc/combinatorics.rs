@@ -0,0 +1,191 @@
+use std::f64::consts::{LN_2, LOG2_E};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use crate::calc::integer::{self, MAX_BITS};
+use crate::calc::special;
+
+// Binomial coefficients with at most this many factors are multiplied out one by one
+const DIRECT_TERMS: u64 = 1000;
+// Larger ones up to this n are built from their prime factorisation
+const SIEVE_LIMIT: u64 = 1 << 24;
+
+// Exact counting functions for whole numbers of any size. Errors are messages for
+// Error::Domain, and results beyond integer::MAX_BITS are refused before computing them.
+
+// Ways to pick k of n items in any order: n (n - 1) ... (n - k + 1) / k!. The same product
+// extends to negative n, so ncr(-1, k) is (-1)^k.
+pub(super) fn choose(n: &BigInt, k: &BigInt) -> Result<BigInt, String> {
+    if k.is_negative() || !n.is_negative() && k > n {
+        return Ok(BigInt::zero());
+    }
+    if n.is_negative() {
+        // C(-m, k) = (-1)^k C(m + k - 1, k)
+        let result = binomial(&(k - n - 1), k)?;
+        return Ok(if k.is_odd() { -result } else { result });
+    }
+    binomial(n, k)
+}
+
+// Ways to arrange k of n items in order: n (n - 1) ... (n - k + 1)
+pub(super) fn permutations(n: &BigInt, k: &BigInt) -> Result<BigInt, String> {
+    if k.is_negative() {
+        return Err(format!("{k} is negative"));
+    }
+    if !n.is_negative() && k > n {
+        return Ok(BigInt::zero());
+    }
+    let terms = k.to_u64().ok_or_else(too_large)?;
+    if n.is_negative() {
+        // -m (-m - 1) ... (-m - k + 1) = (-1)^k (m + k - 1) ... m
+        let result = falling(&(k - n - 1), terms)?;
+        return Ok(if k.is_odd() { -result } else { result });
+    }
+    falling(n, terms)
+}
+
+// Ways to split k1 + k2 + ... items into groups of those sizes: (k1 + k2 + ...)! / (k1! k2! ...),
+// built up as a product of binomial coefficients
+pub(super) fn multinomial(counts: &[BigInt]) -> Result<BigInt, String> {
+    let mut result = BigInt::one();
+    let mut total = BigInt::zero();
+    for count in counts {
+        if count.is_negative() {
+            return Err(format!("{count} is negative"));
+        }
+        total += count;
+        result *= binomial(&total, count)?;
+        if result.bits() > MAX_BITS {
+            return Err(too_large());
+        }
+    }
+    Ok(result)
+}
+
+// Permutations of n items that leave none in place: !n = n !(n - 1) + (-1)^n, about n! / e
+pub(super) fn subfactorial(n: &BigInt) -> Result<BigInt, String> {
+    if n.is_negative() {
+        return Err(format!("{n} is negative"));
+    }
+    let n = n.to_u64().ok_or_else(too_large)?;
+    check_bits(factorial_bits(n) - LOG2_E)?;
+    let mut result = BigInt::one();
+    for i in 1..=n {
+        result *= i;
+        if i.is_even() {
+            result += 1;
+        } else {
+            result -= 1;
+        }
+    }
+    Ok(result)
+}
+
+// C(n, k) for 0 <= k <= n
+fn binomial(n: &BigInt, k: &BigInt) -> Result<BigInt, String> {
+    // C(n, k) = C(n, n - k), so the shorter product is used
+    let k = k.min(&(n - k)).to_u64().ok_or_else(too_large)?;
+    check_bits(falling_bits(n, k) - factorial_bits(k))?;
+
+    match n.to_u64() {
+        Some(n) if k > DIRECT_TERMS && n <= SIEVE_LIMIT => Ok(from_primes(n, k)),
+        Some(n) if k > DIRECT_TERMS => Ok(integer::product(n - k + 1, n) / integer::product(1, k)),
+        _ => {
+            // Every partial product C(n, i) is a whole number
+            let mut result = BigInt::one();
+            for i in 0..k {
+                result = result * (n - i) / (i + 1);
+            }
+            Ok(result)
+        }
+    }
+}
+
+// n (n - 1) ... (n - k + 1) for 0 <= k <= n
+fn falling(n: &BigInt, k: u64) -> Result<BigInt, String> {
+    check_bits(falling_bits(n, k))?;
+    match n.to_u64() {
+        Some(n) => Ok(integer::product(n - k + 1, n)),
+        None => Ok((0..k).map(|i| n - i).product()),
+    }
+}
+
+// C(n, k) from its prime factorisation, where the power of p in n! is n/p + n/p^2 + ...
+fn from_primes(n: u64, k: u64) -> BigInt {
+    let powers: Vec<BigInt> = primes_up_to(n)
+        .into_iter()
+        .filter_map(|p| {
+            let exponent = legendre(n, p) - legendre(k, p) - legendre(n - k, p);
+            (exponent > 0).then(|| BigInt::from(p).pow(exponent))
+        })
+        .collect();
+    product_of(&powers)
+}
+
+fn legendre(n: u64, p: u64) -> u32 {
+    let mut exponent = 0;
+    let mut rest = n / p;
+    while rest > 0 {
+        exponent += rest as u32;
+        rest /= p;
+    }
+    exponent
+}
+
+// Sieve of Eratosthenes
+fn primes_up_to(n: u64) -> Vec<u64> {
+    let n = n as usize;
+    let mut composite = vec![false; n + 1];
+    let mut primes = Vec::new();
+    for i in 2..=n {
+        if composite[i] {
+            continue;
+        }
+        primes.push(i as u64);
+        for multiple in (i * i..=n).step_by(i) {
+            composite[multiple] = true;
+        }
+    }
+    primes
+}
+
+// Multiplies in halves, so the large multiplications are between numbers of similar size
+fn product_of(values: &[BigInt]) -> BigInt {
+    match values {
+        [] => BigInt::one(),
+        [value] => value.clone(),
+        _ => {
+            let (low, high) = values.split_at(values.len() / 2);
+            product_of(low) * product_of(high)
+        }
+    }
+}
+
+// log2 of n (n - 1) ... (n - k + 1), to refuse huge results before computing them
+fn falling_bits(n: &BigInt, k: u64) -> f64 {
+    match n.to_f64() {
+        Some(x) if x < 2f64.powi(53) => {
+            (special::ln_gamma(x + 1.0) - special::ln_gamma(x - k as f64 + 1.0)) / LN_2
+        }
+        // Each factor is at least n / 2 when k <= n / 2, and a larger k is hopeless anyway
+        _ => k as f64 * (n.bits() - 2) as f64,
+    }
+}
+
+fn factorial_bits(n: u64) -> f64 {
+    special::ln_gamma(n as f64 + 1.0) / LN_2
+}
+
+fn check_bits(bits: f64) -> Result<(), String> {
+    if bits > MAX_BITS as f64 {
+        Err(too_large())
+    } else {
+        Ok(())
+    }
+}
+
+fn too_large() -> String {
+    "result is too large".to_string()
+}
@@ -0,0 +1,263 @@
+use std::f64::consts::PI;
+
+use num_complex::Complex64;
+use num_traits::Zero;
+
+use crate::calc::error::{Error, math_error};
+use crate::calc::parser::{BinaryOperator, Function, UnaryOperator};
+use crate::calc::special::{LANCZOS_COEFFICIENTS, LANCZOS_G};
+use crate::calc::value::Value;
+
+// Complex versions of the operators, using the principal branch wherever a function has
+// several
+pub(super) fn unary(operation: &UnaryOperator, z: Complex64) -> Result<Complex64, Error> {
+    Ok(match operation {
+        UnaryOperator::Factorial => gamma(z + 1.0),
+        UnaryOperator::DoubleFactorial => double_factorial(z),
+        UnaryOperator::SquareRoot => z.sqrt(),
+        UnaryOperator::Sin => z.sin(),
+        UnaryOperator::Cos => z.cos(),
+        UnaryOperator::Tan => z.tan(),
+        UnaryOperator::Sec => reciprocal(z.cos())?,
+        UnaryOperator::Csc => reciprocal(z.sin())?,
+        UnaryOperator::Cot => reciprocal(z.tan())?,
+        UnaryOperator::Asin => z.asin(),
+        UnaryOperator::Acos => z.acos(),
+        // Infinite at the branch points ±i
+        UnaryOperator::Atan if z == Complex64::I || z == -Complex64::I => {
+            return Err(math_error("inverse tangent of ±i encountered"));
+        }
+        UnaryOperator::Atan => z.atan(),
+        UnaryOperator::Sinh => z.sinh(),
+        UnaryOperator::Cosh => z.cosh(),
+        UnaryOperator::Tanh => z.tanh(),
+        UnaryOperator::Asinh => z.asinh(),
+        UnaryOperator::Acosh => z.acosh(),
+        // Infinite at the branch points ±1
+        UnaryOperator::Atanh if z == Complex64::ONE || z == -Complex64::ONE => {
+            return Err(math_error("inverse hyperbolic tangent of ±1 encountered"));
+        }
+        UnaryOperator::Atanh => z.atanh(),
+        UnaryOperator::Ln if z.is_zero() => {
+            return Err(math_error("natural logarithm of zero encountered"));
+        }
+        UnaryOperator::Ln => z.ln(),
+        // Rounding applies to both parts
+        UnaryOperator::Floor => Complex64::new(z.re.floor(), z.im.floor()),
+        UnaryOperator::Ceil => Complex64::new(z.re.ceil(), z.im.ceil()),
+        UnaryOperator::Round => Complex64::new(z.re.round(), z.im.round()),
+        UnaryOperator::Abs => z.norm().into(), // Modulus: |a + bi| = sqrt(a^2 + b^2)
+        UnaryOperator::Negate => -z,
+        UnaryOperator::Not if z.is_nan() => nan(),
+        UnaryOperator::Not => truth(z.is_zero()),
+        UnaryOperator::Gamma => gamma(z),
+        UnaryOperator::LogGamma => ln_gamma(z),
+        UnaryOperator::Digamma => digamma(z),
+        UnaryOperator::Real => z.re.into(),
+        UnaryOperator::Imaginary => z.im.into(),
+        UnaryOperator::Argument => z.arg().into(), // Angle in (-pi, pi]
+        UnaryOperator::Conjugate => z.conj(),
+        UnaryOperator::Angle(_) => unreachable!(),
+    })
+}
+
+pub(super) fn binary(
+    operation: &BinaryOperator,
+    left: Complex64,
+    right: Complex64,
+) -> Result<Complex64, Error> {
+    Ok(match operation {
+        BinaryOperator::Add => left + right,
+        BinaryOperator::Subtract => left - right,
+        BinaryOperator::Multiply => left * right,
+        BinaryOperator::Divide if right.is_zero() => {
+            return Err(math_error("division by zero encountered"));
+        }
+        BinaryOperator::Divide => left / right,
+        BinaryOperator::Modulo => return Err(math_error("modulo of complex numbers is undefined")),
+        BinaryOperator::Power => power(left, right)?,
+        BinaryOperator::Log => logarithm(left, right)?,
+        BinaryOperator::Equal => truth(left == right),
+        BinaryOperator::NotEqual => truth(left != right),
+        BinaryOperator::Less
+        | BinaryOperator::LessEqual
+        | BinaryOperator::Greater
+        | BinaryOperator::GreaterEqual => {
+            return Err(math_error("complex numbers cannot be compared"));
+        }
+        BinaryOperator::And | BinaryOperator::Or => truth(!right.is_zero()),
+        BinaryOperator::MatrixMultiply => unreachable!(),
+    })
+}
+
+// Functions with an argument list, other than if, max and min
+pub(super) fn call(function: &Function, arguments: &[Complex64]) -> Result<Complex64, Error> {
+    Ok(match function {
+        Function::Log => logarithm(
+            arguments[0],
+            arguments.get(1).copied().unwrap_or(10.0.into()),
+        )?,
+        Function::Atan2 => return Err(math_error("atan2 of complex numbers is undefined")),
+        Function::Hypot => (arguments[0] * arguments[0] + arguments[1] * arguments[1]).sqrt(),
+        Function::Beta => {
+            let (a, b) = (arguments[0], arguments[1]);
+            gamma(a) * gamma(b) / gamma(a + b)
+        }
+        // Unary functions are always parsed into Token::Unary, the rest only take real numbers
+        // or are handled by solve
+        _ => unreachable!(),
+    })
+}
+
+// Whether a real operand only has a complex result, so complex mode should take over
+pub(super) fn leaves_reals_unary(operation: &UnaryOperator, operand: f64) -> bool {
+    match operation {
+        UnaryOperator::SquareRoot | UnaryOperator::Ln => operand < 0.0,
+        UnaryOperator::Asin | UnaryOperator::Acos | UnaryOperator::Atanh => operand.abs() > 1.0,
+        UnaryOperator::Acosh => operand < 1.0,
+        _ => false,
+    }
+}
+
+pub(super) fn leaves_reals_binary(operation: &BinaryOperator, left: f64, right: f64) -> bool {
+    match operation {
+        // A negative base only has real powers for whole exponents
+        BinaryOperator::Power => left < 0.0 && right.fract() != 0.0,
+        BinaryOperator::Log => left < 0.0 || right < 0.0,
+        _ => false,
+    }
+}
+
+pub(super) fn leaves_reals_call(function: &Function, arguments: &[f64]) -> bool {
+    match function {
+        Function::Log => arguments.iter().any(|&argument| argument < 0.0),
+        _ => false,
+    }
+}
+
+// Results with no imaginary part are real numbers again, so i * i is -1
+pub(super) fn value(z: Complex64) -> Value {
+    if z.im == 0.0 {
+        Value::Number(z.re)
+    } else {
+        Value::Complex(z)
+    }
+}
+
+// Rectangular form: 3 + 4i, 3 - 4i, 4i
+pub(super) fn rectangular(z: &Complex64) -> String {
+    let imaginary = match z.im.abs() {
+        1.0 => "i".to_string(),
+        im => format!("{}i", Value::Number(im)),
+    };
+    match (z.re, z.im < 0.0) {
+        (0.0, false) => imaginary,
+        (0.0, true) => format!("-{imaginary}"),
+        (re, false) => format!("{} + {imaginary}", Value::Number(re)),
+        (re, true) => format!("{} - {imaginary}", Value::Number(re)),
+    }
+}
+
+// Polar form r e^(θi), which can be entered again as it is shown
+pub(super) fn polar(z: &Complex64) -> String {
+    let (modulus, argument) = z.to_polar();
+    format!("{}e^({}i)", Value::Number(modulus), Value::Number(argument))
+}
+
+fn power(base: Complex64, exponent: Complex64) -> Result<Complex64, Error> {
+    if base.is_zero() {
+        return if exponent.im == 0.0 && exponent.re > 0.0 {
+            Ok(Complex64::ZERO)
+        } else {
+            Err(math_error("zero raised to a non-positive or complex power"))
+        };
+    }
+    // Whole exponents multiply exactly, so i^2 is exactly -1
+    if exponent.im == 0.0 && exponent.re.fract() == 0.0 && exponent.re.abs() <= i32::MAX as f64 {
+        return Ok(base.powi(exponent.re as i32));
+    }
+    Ok(base.powc(exponent))
+}
+
+fn logarithm(value: Complex64, base: Complex64) -> Result<Complex64, Error> {
+    if value.is_zero() || base.is_zero() || base == Complex64::ONE {
+        Err(math_error("invalid logarithm base or argument"))
+    } else {
+        Ok(value.ln() / base.ln())
+    }
+}
+
+// Lanczos approximation, with the reflection formula Γ(z) Γ(1 - z) = π / sin(πz) on the left
+fn gamma(z: Complex64) -> Complex64 {
+    if z.re < 0.5 {
+        return PI / ((PI * z).sin() * gamma(1.0 - z));
+    }
+    let (sum, t) = lanczos(z);
+    (2.0 * PI).sqrt() * sum * t.powc(z - 0.5) * (-t).exp()
+}
+
+// Same continuation as the floating point x!!
+fn double_factorial(z: Complex64) -> Complex64 {
+    let weight = (1.0 - (PI * z).cos()) / 4.0;
+    Complex64::from(2.0).powc(z / 2.0)
+        * Complex64::from(2.0 / PI).powc(weight)
+        * gamma(z / 2.0 + 1.0)
+}
+
+// ln Γ(z), continuing the real lgamma: the imaginary part is not limited to (-pi, pi]
+// right of 1/2, where Γ(z) itself would overflow
+fn ln_gamma(z: Complex64) -> Complex64 {
+    if z.re < 0.5 {
+        return gamma(z).ln();
+    }
+    let (sum, t) = lanczos(z);
+    (2.0 * PI).sqrt().ln() + sum.ln() + (z - 0.5) * t.ln() - t
+}
+
+// Same series as the floating point ψ(x), with ψ(1 - z) - ψ(z) = π cot(πz) on the left
+fn digamma(z: Complex64) -> Complex64 {
+    if z.re < 0.0 {
+        return digamma(1.0 - z) - PI / (PI * z).tan();
+    }
+
+    let mut z = z;
+    let mut result = Complex64::ZERO;
+    while z.re < 10.0 {
+        result -= 1.0 / z;
+        z += 1.0;
+    }
+    let inverse_square = 1.0 / (z * z);
+    let series = inverse_square
+        * (1.0 / 12.0
+            - inverse_square
+                * (1.0 / 120.0
+                    - inverse_square
+                        * (1.0 / 252.0 - inverse_square * (1.0 / 240.0 - inverse_square / 132.0))));
+    result + z.ln() - 0.5 / z - series
+}
+
+// The Lanczos sum for Γ(z) and the matching t = z + g - 1/2, for Re z >= 0.5
+fn lanczos(z: Complex64) -> (Complex64, Complex64) {
+    let z = z - 1.0;
+    let mut sum = Complex64::from(LANCZOS_COEFFICIENTS[0]);
+    for (i, coefficient) in LANCZOS_COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += coefficient / (z + i as f64);
+    }
+    (sum, z + LANCZOS_G + 0.5)
+}
+
+fn reciprocal(z: Complex64) -> Result<Complex64, Error> {
+    if z.is_zero() {
+        Err(math_error("division by zero encountered"))
+    } else {
+        Ok(1.0 / z)
+    }
+}
+
+fn truth(condition: bool) -> Complex64 {
+    (condition as u8 as f64).into()
+}
+
+fn nan() -> Complex64 {
+    Complex64::new(f64::NAN, f64::NAN)
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::calc::parser::Token;
+use crate::calc::settings::Settings;
+use crate::calc::value::Value;
+
+// A function defined with `name(a, b) = body`
+pub(super) struct UserFunction {
+    pub(super) parameters: Vec<String>,
+    pub(super) body: Token,
+    pub(super) source: String,
+}
+
+// State carried between evaluations: the last result, user-defined variables and functions,
+// and the settings they are evaluated with
+#[derive(Default)]
+pub(crate) struct Context {
+    pub(crate) last_result: Option<Value>,
+    pub(crate) variables: HashMap<String, Value>,
+    pub(super) functions: HashMap<String, Arc<UserFunction>>,
+    // Parameter bindings of the user function calls being evaluated, innermost last
+    pub(super) frames: Vec<HashMap<String, Value>>,
+    // Terms of sums and products evaluated in the current statement, nested ones included
+    pub(super) series_terms: u32,
+    pub(crate) settings: Settings,
+}
+
+impl Context {
+    // Parameters of the current call shadow global variables
+    pub(super) fn lookup(&self, name: &str) -> Option<Value> {
+        self.frames
+            .last()
+            .and_then(|frame| frame.get(name))
+            .or_else(|| self.variables.get(name))
+            .cloned()
+    }
+
+    // Parameter counts of the user functions, as needed by the parser
+    pub(super) fn function_arities(&self) -> HashMap<String, usize> {
+        self.functions
+            .iter()
+            .map(|(name, function)| (name.clone(), function.parameters.len()))
+            .collect()
+    }
+}
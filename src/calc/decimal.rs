@@ -0,0 +1,1052 @@
+use std::cmp::Ordering;
+use std::f64::consts::LN_10;
+use std::fmt;
+
+use num_bigint::{BigInt, Sign};
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::calc::integer;
+use crate::calc::parser::{BinaryOperator, Function, UnaryOperator};
+use crate::calc::settings::AngleUnit;
+
+// Extra digits carried through intermediate steps so the rounded result is accurate
+const GUARD_DIGITS: usize = 10;
+
+// Largest Newton iterations in ln before giving up on the last digit
+const MAX_ITERATIONS: usize = 32;
+
+// Largest decimal exponent of a result, the number of digits of 2^MAX_BITS. The calculator
+// refuses larger and smaller results up front, the checks here only keep the work bounded.
+pub(super) const MAX_EXPONENT: i64 = integer::MAX_BITS as i64 * 30_103 / 100_000;
+
+// Largest n for which n! is multiplied out, larger ones go through Stirling's series
+const MAX_PRODUCT: u64 = 10_000;
+
+// Zeros written out before Display switches to scientific notation
+const MAX_PADDING: i64 = 30;
+
+/// Most significant digits the command line allows for [`Mode::Decimal`](crate::Mode::Decimal).
+/// Each digit makes every operation slower, and 1000 digits still evaluate in well under a
+/// second.
+pub const MAX_PRECISION: usize = 1000;
+
+/// Decimal floating point number `mantissa * 10^exponent`, as used in
+/// [`Mode::Decimal`](crate::Mode::Decimal).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decimal {
+    mantissa: BigInt,
+    exponent: i64,
+}
+
+impl Decimal {
+    // Trailing zeros are moved into the exponent, so equal numbers compare equal
+    fn new(mut mantissa: BigInt, mut exponent: i64) -> Self {
+        if mantissa.is_zero() {
+            return Self::zero();
+        }
+        let ten = BigInt::from(10);
+        loop {
+            let (quotient, remainder) = mantissa.div_rem(&ten);
+            if !remainder.is_zero() {
+                break;
+            }
+            mantissa = quotient;
+            exponent += 1;
+        }
+        Self { mantissa, exponent }
+    }
+
+    fn zero() -> Self {
+        Self {
+            mantissa: BigInt::zero(),
+            exponent: 0,
+        }
+    }
+
+    pub(crate) fn from_integer(n: impl Into<BigInt>) -> Self {
+        Self::new(n.into(), 0)
+    }
+
+    pub(crate) fn from_rational(value: &BigRational, digits: usize) -> Self {
+        let numerator = Self::from_integer(value.numer().clone());
+        numerator.div(&Self::from_integer(value.denom().clone()), digits)
+    }
+
+    // Uses the shortest decimal that reads back as the same f64, so 0.1 stays 0.1
+    pub(crate) fn from_f64(n: f64) -> Option<Self> {
+        if !n.is_finite() {
+            return None;
+        }
+        let text = format!("{:e}", n);
+        let (mantissa, exponent) = text.split_once('e')?;
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let mantissa: BigInt = format!("{whole}{fraction}").parse().ok()?;
+        let exponent = exponent.parse::<i64>().ok()? - fraction.len() as i64;
+        Some(Self::new(mantissa, exponent))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        format!("{}e{}", self.mantissa, self.exponent)
+            .parse()
+            .unwrap_or(f64::NAN)
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    fn is_negative(&self) -> bool {
+        self.mantissa.is_negative()
+    }
+
+    // Position of the leading digit, e.g. 2 for 123 and -2 for 0.015
+    fn top(&self) -> i64 {
+        self.exponent + digit_count(&self.mantissa) as i64 - 1
+    }
+
+    // log10 |x|, also beyond the range of f64
+    pub(super) fn log10(&self) -> f64 {
+        let shift = digit_count(&self.mantissa).saturating_sub(17);
+        let leading = &self.mantissa.abs() / power_of_ten(shift);
+        leading.to_f64().unwrap_or_default().log10() + (self.exponent + shift as i64) as f64
+    }
+
+    // The exact integer value, if this is a whole number of reasonable size
+    pub(crate) fn to_integer(&self) -> Option<BigInt> {
+        match self.exponent {
+            0..=100_000 => Some(&self.mantissa * power_of_ten(self.exponent as usize)),
+            _ => None,
+        }
+    }
+
+    // The exact fraction, if the exponent is of reasonable size
+    pub(crate) fn to_rational(&self) -> Option<BigRational> {
+        match self.exponent {
+            0..=100_000 => self.to_integer().map(BigRational::from_integer),
+            -100_000..0 => Some(BigRational::new(
+                self.mantissa.clone(),
+                power_of_ten(self.exponent.unsigned_abs() as usize),
+            )),
+            _ => None,
+        }
+    }
+
+    // Rounds to the given number of significant digits, halves away from zero
+    fn round(&self, digits: usize) -> Self {
+        let count = digit_count(&self.mantissa);
+        if count <= digits {
+            return self.clone();
+        }
+        let dropped = count - digits;
+        let divisor = power_of_ten(dropped);
+        let (mut quotient, remainder) = self.mantissa.div_rem(&divisor);
+        if remainder.abs() * 2 >= divisor {
+            quotient += self.mantissa.signum();
+        }
+        Self::new(quotient, self.exponent + dropped as i64)
+    }
+
+    fn neg(&self) -> Self {
+        Self::new(-&self.mantissa, self.exponent)
+    }
+
+    fn abs(&self) -> Self {
+        Self::new(self.mantissa.abs(), self.exponent)
+    }
+
+    fn add(&self, other: &Self, digits: usize) -> Self {
+        if self.is_zero() || other.is_zero() {
+            let nonzero = if self.is_zero() { other } else { self };
+            return nonzero.round(digits);
+        }
+
+        // An addend entirely below the rounding position only matters for rounding,
+        // so it is replaced by a small stand-in instead of aligning every digit
+        let (large, small) = if self.top() >= other.top() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let cutoff = large.top() - digits as i64 - 2;
+        let small = if small.top() < cutoff {
+            Self::new(small.mantissa.signum(), cutoff - 1)
+        } else {
+            small.clone()
+        };
+
+        let exponent = large.exponent.min(small.exponent);
+        let sum = align(large, exponent) + align(&small, exponent);
+        Self::new(sum, exponent).round(digits)
+    }
+
+    fn sub(&self, other: &Self, digits: usize) -> Self {
+        self.add(&other.neg(), digits)
+    }
+
+    fn mul(&self, other: &Self, digits: usize) -> Self {
+        Self::new(
+            &self.mantissa * &other.mantissa,
+            self.exponent + other.exponent,
+        )
+        .round(digits)
+    }
+
+    // Callers must rule out division by zero
+    fn div(&self, other: &Self, digits: usize) -> Self {
+        if self.is_zero() {
+            return Self::zero();
+        }
+        let count = digit_count(&self.mantissa);
+        let shift = (digits + 2 + digit_count(&other.mantissa)).saturating_sub(count);
+        let scaled = &self.mantissa * power_of_ten(shift);
+        let (quotient, remainder) = scaled.div_rem(&other.mantissa);
+        let exponent = self.exponent - other.exponent - shift as i64;
+
+        // An inexact quotient gets a nonzero digit appended so halves round correctly
+        if remainder.is_zero() {
+            Self::new(quotient, exponent).round(digits)
+        } else {
+            let sticky = self.mantissa.signum() * other.mantissa.signum();
+            Self::new(quotient * 10 + sticky, exponent - 1).round(digits)
+        }
+    }
+
+    // Callers must rule out negative numbers
+    fn sqrt(&self, digits: usize) -> Self {
+        if self.is_zero() {
+            return Self::zero();
+        }
+        let count = digit_count(&self.mantissa);
+        let mut shift = (2 * (digits + 2)).saturating_sub(count) as i64;
+        if (self.exponent - shift).rem_euclid(2) != 0 {
+            shift += 1;
+        }
+        let scaled = &self.mantissa * power_of_ten(shift as usize);
+        let root = scaled.sqrt();
+        let exponent = (self.exponent - shift) / 2;
+
+        if &root * &root == scaled {
+            Self::new(root, exponent).round(digits)
+        } else {
+            Self::new(root * 10 + 1, exponent - 1).round(digits)
+        }
+    }
+
+    // Exact remainder with the sign of the dividend, like f64 %
+    fn rem(&self, other: &Self) -> Self {
+        let exponent = self.exponent.min(other.exponent);
+        let remainder = align(self, exponent) % align(other, exponent);
+        Self::new(remainder, exponent)
+    }
+
+    fn floor(&self) -> Self {
+        self.to_whole(|mantissa, divisor| mantissa.div_floor(divisor))
+    }
+
+    fn ceil(&self) -> Self {
+        self.to_whole(|mantissa, divisor| mantissa.div_ceil(divisor))
+    }
+
+    // Halves round away from zero, like f64::round
+    fn round_whole(&self) -> Self {
+        self.to_whole(|mantissa, divisor| {
+            let (quotient, remainder) = mantissa.div_rem(divisor);
+            if remainder.abs() * 2 >= *divisor {
+                quotient + mantissa.signum()
+            } else {
+                quotient
+            }
+        })
+    }
+
+    fn to_whole(&self, divide: impl Fn(&BigInt, &BigInt) -> BigInt) -> Self {
+        if self.exponent >= 0 {
+            return self.clone();
+        }
+        // Below 0.01 in magnitude only the sign matters, which avoids huge divisors for 1e-9999
+        let (mantissa, exponent) = if self.top() < -2 {
+            (self.mantissa.signum(), -3)
+        } else {
+            (self.mantissa.clone(), self.exponent)
+        };
+        Self::from_integer(divide(&mantissa, &power_of_ten(-exponent as usize)))
+    }
+
+    // Integer powers by repeated squaring, None if the result is out of range
+    fn powi(&self, exponent: &BigInt, digits: usize) -> Option<Self> {
+        let n = exponent.to_i64()?;
+        if self.is_zero() && n < 0 {
+            return None;
+        }
+        if !self.is_zero() && (self.log10() * n as f64).abs() > MAX_EXPONENT as f64 {
+            return None;
+        }
+
+        let working = digits + GUARD_DIGITS + digit_count(exponent);
+        let mut result = Self::from_integer(1);
+        let mut base = self.clone();
+        let mut remaining = n.unsigned_abs();
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = result.mul(&base, working);
+            }
+            base = base.mul(&base, working);
+            remaining >>= 1;
+        }
+
+        let result = if n < 0 {
+            Self::from_integer(1).div(&result, working)
+        } else {
+            result
+        };
+        Some(result.round(digits))
+    }
+
+    // Real powers through exp(y * ln(x)), None outside the real domain
+    fn powf(&self, exponent: &Self, digits: usize) -> Option<Self> {
+        if exponent.exponent >= 0 {
+            return self.powi(&exponent.to_integer()?, digits);
+        }
+        if self.is_negative() || (self.is_zero() && exponent.is_negative()) {
+            return None;
+        }
+        if self.is_zero() {
+            return Some(Self::zero());
+        }
+        let working = digits + GUARD_DIGITS + self.top().unsigned_abs().to_string().len();
+        let logarithm = self.ln(working)?;
+        let result = exponent.mul(&logarithm, working).exp(working)?;
+        Some(result.round(digits))
+    }
+
+    // None if the result is too large to represent
+    fn exp(&self, digits: usize) -> Option<Self> {
+        if self.to_f64().abs() > MAX_EXPONENT as f64 * LN_10 {
+            return None;
+        }
+
+        // exp(x) = exp(x / 2^k)^(2^k), with x / 2^k small enough for the series to converge quickly
+        let magnitude = self.to_f64().abs();
+        let halvings = if magnitude > 0.01 {
+            (magnitude * 100.0).log2().ceil() as u32
+        } else {
+            0
+        };
+        let working = digits + GUARD_DIGITS + halvings as usize / 3 + 1;
+        let x = self.div(&Self::from_integer(BigInt::from(2).pow(halvings)), working);
+
+        let mut sum = Self::from_integer(1);
+        let mut term = Self::from_integer(1);
+        for n in 1.. {
+            term = term.mul(&x, working).div(&Self::from_integer(n), working);
+            if term.is_zero() || term.top() < sum.top() - working as i64 {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        for _ in 0..halvings {
+            sum = sum.mul(&sum, working);
+        }
+        Some(sum.round(digits))
+    }
+
+    // None outside the domain (x <= 0)
+    fn ln(&self, digits: usize) -> Option<Self> {
+        if self.is_negative() || self.is_zero() {
+            return None;
+        }
+
+        // ln(x) = ln(y) + k * ln(10) with x = y * 10^k and 1 <= y < 10
+        let k = self.top();
+        let working = digits + GUARD_DIGITS + k.unsigned_abs().to_string().len();
+        let y = Self::new(self.mantissa.clone(), self.exponent - k);
+        let mut result = y.ln_reduced(working);
+        if k != 0 {
+            let ln_ten = Self::from_integer(10).ln_reduced(working);
+            result = result.add(&ln_ten.mul(&Self::from_integer(k), working), working);
+        }
+        Some(result.round(digits))
+    }
+
+    // Halley's iteration z += 2 (y - e^z) / (y + e^z), which triples the correct digits each step
+    fn ln_reduced(&self, digits: usize) -> Self {
+        let two = Self::from_integer(2);
+        let mut z = Self::from_f64(self.to_f64().ln()).unwrap_or_else(Self::zero);
+        for _ in 0..MAX_ITERATIONS {
+            let exp_z = z.exp(digits).unwrap_or_else(Self::zero);
+            let correction = self
+                .sub(&exp_z, digits)
+                .div(&self.add(&exp_z, digits), digits)
+                .mul(&two, digits);
+            z = z.add(&correction, digits);
+            if correction.is_zero() || correction.top() < z.top() - digits as i64 {
+                break;
+            }
+        }
+        z
+    }
+
+    // None for angles beyond is_reducible
+    fn sin(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let x = self.reduce_angle(working)?;
+        let x_squared = x.mul(&x, working);
+
+        // sin(x) = x - x^3/3! + x^5/5! - ...
+        let mut sum = x.clone();
+        let mut term = x;
+        for n in 1.. {
+            let divisor = Self::from_integer((2 * n) * (2 * n + 1));
+            term = term.mul(&x_squared, working).div(&divisor, working).neg();
+            if term.is_zero() || term.top() < sum.top() - working as i64 {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        Some(sum.round(digits))
+    }
+
+    fn cos(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let x = self.reduce_angle(working)?;
+        let x_squared = x.mul(&x, working);
+
+        // cos(x) = 1 - x^2/2! + x^4/4! - ...
+        let mut sum = Self::from_integer(1);
+        let mut term = Self::from_integer(1);
+        for n in 1.. {
+            let divisor = Self::from_integer((2 * n - 1) * (2 * n));
+            term = term.mul(&x_squared, working).div(&divisor, working).neg();
+            if term.is_zero() || term.top() < sum.top() - working as i64 {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        Some(sum.round(digits))
+    }
+
+    fn tan(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let cos = self.cos(working)?;
+        if cos.is_zero() {
+            return None;
+        }
+        Some(self.sin(working)?.div(&cos, digits))
+    }
+
+    // Subtracts whole turns so |x| <= pi, with pi precise enough for the integer part of x
+    fn reduce_angle(&self, digits: usize) -> Option<Self> {
+        if self.top() < 0 {
+            return Some(self.clone());
+        }
+        if !is_reducible(self) {
+            return None;
+        }
+        let working = digits + self.top() as usize + 1;
+        let two_pi = pi(working).mul(&Self::from_integer(2), working);
+        let turns = self.div(&two_pi, working).round_whole();
+        Some(self.sub(&two_pi.mul(&turns, working), working))
+    }
+
+    fn atan(&self, digits: usize) -> Self {
+        let working = digits + GUARD_DIGITS;
+        let one = Self::from_integer(1);
+
+        // atan(x) = ±pi/2 - atan(1/x) for |x| > 1
+        let result = match self.abs().cmp(&one) {
+            Ordering::Greater => {
+                let half_pi = pi(working).div(&Self::from_integer(2), working);
+                let inverse = one.div(self, working).atan_reduced(working);
+                if self.is_negative() {
+                    half_pi.neg().sub(&inverse, working)
+                } else {
+                    half_pi.sub(&inverse, working)
+                }
+            }
+            _ => self.atan_reduced(working),
+        };
+        result.round(digits)
+    }
+
+    // Γ(x) = Γ(x + m) / (x (x + 1) ... (x + m - 1)), with x + m large enough for Stirling's
+    // series. Callers must rule out the poles at zero and the negative integers.
+    fn gamma(&self, digits: usize) -> Option<Self> {
+        if let Some(n) = self.to_integer()
+            && n.to_u64().is_some_and(|n| n <= MAX_PRODUCT)
+        {
+            // Whole numbers are exact: Γ(n) = (n - 1)!
+            return factorial(&Self::from_integer(n - 1), digits);
+        }
+        let working = digits + GUARD_DIGITS;
+        let (shifted, product) = self.shift_up(working)?;
+        let result = shifted.ln_gamma_series(working)?.exp(working)?;
+        Some(result.div(&product, digits))
+    }
+
+    // ln |Γ(x)|, which stays finite where Γ(x) is too large to represent
+    fn ln_gamma(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let (shifted, product) = self.shift_up(working)?;
+        let result = shifted
+            .ln_gamma_series(working)?
+            .sub(&product.abs().ln(working)?, working);
+        Some(result.round(digits))
+    }
+
+    // ψ(x) = ψ(x + m) - 1/x - 1/(x + 1) - ... - 1/(x + m - 1), with the asymptotic series
+    // ψ(z) = ln z - 1/(2z) - sum B_2k / (2k z^2k) for the large z = x + m
+    fn digamma(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let (mut z, _) = self.shift_up(working)?;
+        let one = Self::from_integer(1);
+        let mut result = z.ln(working)?.sub(
+            &one.div(&z.mul(&Self::from_integer(2), working), working),
+            working,
+        );
+
+        let z_squared = z.mul(&z, working);
+        let mut power = z_squared.clone();
+        let mut bernoulli = Vec::new();
+        for k in 1.. {
+            let b = Self::from_rational(&next_bernoulli(&mut bernoulli), working);
+            let term = b.div(&power.mul(&Self::from_integer(2 * k), working), working);
+            if term.is_zero() || term.top() < result.top() - working as i64 {
+                break;
+            }
+            result = result.sub(&term, working);
+            power = power.mul(&z_squared, working);
+        }
+
+        while z > *self {
+            z = z.sub(&one, working);
+            result = result.sub(&one.div(&z, working), working);
+        }
+        Some(result.round(digits))
+    }
+
+    // Moves x up by a whole number m to at least the given size, also returning
+    // the product x (x + 1) ... (x + m - 1) that was skipped
+    fn shift_up(&self, size: usize) -> Option<(Self, Self)> {
+        let steps = (size as i64 - self.floor().to_integer()?.to_i64()?).max(0);
+        if steps > 100_000 {
+            return None;
+        }
+        let working = size + digit_count(&BigInt::from(steps));
+        let one = Self::from_integer(1);
+        let mut x = self.clone();
+        let mut product = one.clone();
+        for _ in 0..steps {
+            product = product.mul(&x, working);
+            x = x.add(&one, working);
+        }
+        Some((x, product))
+    }
+
+    // Stirling's series ln Γ(z) = (z - 1/2) ln z - z + ln(2π)/2 + sum B_2k / (2k (2k - 1) z^(2k - 1)),
+    // accurate for z larger than the number of digits. Digits are absolute here, since
+    // exponentiating turns an absolute error into a relative one.
+    fn ln_gamma_series(&self, digits: usize) -> Option<Self> {
+        let working = digits + 2 * (self.top().max(0) as usize + 1);
+        let half = Self::new(BigInt::from(5), -1);
+        let two = Self::from_integer(2);
+        let ln_two_pi = pi(working).mul(&two, working).ln(working)?;
+        let mut sum = self
+            .sub(&half, working)
+            .mul(&self.ln(working)?, working)
+            .sub(self, working)
+            .add(&ln_two_pi.div(&two, working), working);
+
+        let z_squared = self.mul(self, working);
+        let mut power = self.clone();
+        let mut bernoulli = Vec::new();
+        for k in 1.. {
+            let b = Self::from_rational(&next_bernoulli(&mut bernoulli), working);
+            let divisor = Self::from_integer(2 * k * (2 * k - 1)).mul(&power, working);
+            let term = b.div(&divisor, working);
+            if term.is_zero() || term.top() < -(digits as i64) - 2 {
+                break;
+            }
+            sum = sum.add(&term, working);
+            power = power.mul(&z_squared, working);
+        }
+        Some(sum)
+    }
+
+    // None outside the domain |x| <= 1
+    fn asin(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let cosine = Self::from_integer(1).sub(&self.mul(self, working), working);
+        if cosine.is_negative() {
+            return None;
+        }
+        Some(atan2(self, &cosine.sqrt(working), digits))
+    }
+
+    // None outside the domain |x| <= 1
+    fn acos(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let sine = Self::from_integer(1).sub(&self.mul(self, working), working);
+        if sine.is_negative() {
+            return None;
+        }
+        Some(atan2(&sine.sqrt(working), self, digits))
+    }
+
+    // None if the result is too large to represent
+    fn sinh(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        if self.top() >= 0 {
+            // (e^x - e^-x) / 2, where the terms are far enough apart not to cancel
+            let exp = self.exp(working)?;
+            let difference = exp.sub(&Self::from_integer(1).div(&exp, working), working);
+            return Some(difference.div(&Self::from_integer(2), digits));
+        }
+
+        // sinh(x) = x + x^3/3! + x^5/5! + ... for |x| < 1
+        let x_squared = self.mul(self, working);
+        let mut sum = self.clone();
+        let mut term = self.clone();
+        for n in 1.. {
+            let divisor = Self::from_integer((2 * n) * (2 * n + 1));
+            term = term.mul(&x_squared, working).div(&divisor, working);
+            if term.is_zero() || term.top() < sum.top() - working as i64 {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        Some(sum.round(digits))
+    }
+
+    // None if the result is too large to represent
+    fn cosh(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let exp = self.exp(working)?;
+        let sum = exp.add(&Self::from_integer(1).div(&exp, working), working);
+        Some(sum.div(&Self::from_integer(2), digits))
+    }
+
+    fn tanh(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        Some(self.sinh(working)?.div(&self.cosh(working)?, digits))
+    }
+
+    fn asinh(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let root = self
+            .mul(self, working)
+            .add(&Self::from_integer(1), working)
+            .sqrt(working);
+        if self.abs() < Self::from_integer(1) {
+            // atanh(x / sqrt(x^2 + 1)) avoids the cancellation in ln(1 + tiny)
+            return self.div(&root, working).atanh(digits);
+        }
+        // ln(|x| + sqrt(x^2 + 1)), with the sign of x
+        let result = self.abs().add(&root, working).ln(digits)?;
+        Some(if self.is_negative() {
+            result.neg()
+        } else {
+            result
+        })
+    }
+
+    // None outside the domain x >= 1
+    fn acosh(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        // 2 asinh(sqrt((x - 1) / 2)) stays accurate near 1
+        let half = self
+            .sub(&Self::from_integer(1), working)
+            .div(&Self::from_integer(2), working);
+        if half.is_negative() {
+            return None;
+        }
+        let result = half.sqrt(working).asinh(working)?;
+        Some(result.mul(&Self::from_integer(2), digits))
+    }
+
+    // None outside the domain |x| < 1
+    fn atanh(&self, digits: usize) -> Option<Self> {
+        let working = digits + GUARD_DIGITS;
+        let one = Self::from_integer(1);
+        if self.abs() >= one {
+            return None;
+        }
+        if self.abs() > Self::new(BigInt::from(5), -1) {
+            // ln((1 + x) / (1 - x)) / 2
+            let ratio = one.add(self, working).div(&one.sub(self, working), working);
+            return Some(ratio.ln(working)?.div(&Self::from_integer(2), digits));
+        }
+
+        // atanh(x) = x + x^3/3 + x^5/5 + ... for |x| <= 1/2
+        let x_squared = self.mul(self, working);
+        let mut sum = self.clone();
+        let mut power = self.clone();
+        for n in 1.. {
+            power = power.mul(&x_squared, working);
+            let term = power.div(&Self::from_integer(2 * n + 1), working);
+            if term.is_zero() || term.top() < sum.top() - working as i64 {
+                break;
+            }
+            sum = sum.add(&term, working);
+        }
+        Some(sum.round(digits))
+    }
+
+    // 1 / x, None for zero
+    fn reciprocal(&self, digits: usize) -> Option<Self> {
+        if self.is_zero() {
+            return None;
+        }
+        Some(Self::from_integer(1).div(self, digits))
+    }
+
+    // For |x| <= 1: halves the angle with atan(x) = 2 atan(x / (1 + sqrt(1 + x^2)))
+    // until the series x - x^3/3 + x^5/5 - ... converges quickly
+    fn atan_reduced(&self, digits: usize) -> Self {
+        let one = Self::from_integer(1);
+        let mut x = self.clone();
+        let mut doublings = 0;
+        while !x.is_zero() && x.top() > -2 {
+            let root = one.add(&x.mul(&x, digits), digits).sqrt(digits);
+            x = x.div(&one.add(&root, digits), digits);
+            doublings += 1;
+        }
+
+        let x_squared = x.mul(&x, digits);
+        let mut sum = x.clone();
+        let mut power = x;
+        for n in 1.. {
+            power = power.mul(&x_squared, digits).neg();
+            let term = power.div(&Self::from_integer(2 * n + 1), digits);
+            if term.is_zero() || term.top() < sum.top() - digits as i64 {
+                break;
+            }
+            sum = sum.add(&term, digits);
+        }
+        sum.mul(&Self::from_integer(BigInt::from(2).pow(doublings)), digits)
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let sign = |d: &Decimal| match d.mantissa.sign() {
+            Sign::Minus => -1,
+            Sign::NoSign => 0,
+            Sign::Plus => 1,
+        };
+        match sign(self).cmp(&sign(other)) {
+            Ordering::Equal if sign(self) == 0 => return Ordering::Equal,
+            Ordering::Equal => {}
+            unequal => return unequal,
+        }
+
+        // Same sign: the leading digit position decides unless it is the same
+        let by_magnitude = match self.top().cmp(&other.top()) {
+            Ordering::Equal => {
+                let exponent = self.exponent.min(other.exponent);
+                align(self, exponent)
+                    .abs()
+                    .cmp(&align(other, exponent).abs())
+            }
+            unequal => unequal,
+        };
+        if self.is_negative() {
+            by_magnitude.reverse()
+        } else {
+            by_magnitude
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Decimal {
+    // Plain notation like f64, e.g. 0.00012 and 1200, unless that takes more than MAX_PADDING
+    // zeros. Then it is scientific notation like 1.2e40.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let digits = self.mantissa.abs().to_string();
+        if self.exponent > MAX_PADDING || self.top() < -MAX_PADDING {
+            let (first, rest) = digits.split_at(1);
+            let point = if rest.is_empty() { "" } else { "." };
+            return write!(f, "{sign}{first}{point}{rest}e{}", self.top());
+        }
+        if self.exponent >= 0 {
+            return write!(f, "{sign}{digits}{}", "0".repeat(self.exponent as usize));
+        }
+
+        let point = digits.len() as i64 + self.exponent;
+        if point > 0 {
+            let (whole, fraction) = digits.split_at(point as usize);
+            write!(f, "{sign}{whole}.{fraction}")
+        } else {
+            write!(f, "{sign}0.{}{digits}", "0".repeat(-point as usize))
+        }
+    }
+}
+
+// pi = 16 atan(1/5) - 4 atan(1/239) (Machin's formula)
+pub(super) fn pi(digits: usize) -> Decimal {
+    let working = digits + GUARD_DIGITS;
+    let one = Decimal::from_integer(1);
+    let fifth = one
+        .div(&Decimal::from_integer(5), working)
+        .atan_reduced(working);
+    let small = one
+        .div(&Decimal::from_integer(239), working)
+        .atan_reduced(working);
+    fifth
+        .mul(&Decimal::from_integer(16), working)
+        .sub(&small.mul(&Decimal::from_integer(4), working), working)
+        .round(digits)
+}
+
+pub(super) fn e(digits: usize) -> Decimal {
+    Decimal::from_integer(1)
+        .exp(digits)
+        .unwrap_or_else(Decimal::zero)
+}
+
+// Rescales an angle by the ratio of half turns, with pi to extra digits so the
+// conversion itself adds no error
+pub(super) fn convert_angle(
+    value: &Decimal,
+    from: AngleUnit,
+    to: AngleUnit,
+    digits: usize,
+) -> Decimal {
+    let working = digits + GUARD_DIGITS;
+    let half_turn = |unit| match unit {
+        AngleUnit::Radians => pi(working),
+        AngleUnit::Degrees => Decimal::from_integer(180),
+        AngleUnit::Gradians => Decimal::from_integer(200),
+    };
+    value
+        .mul(&half_turn(to), working)
+        .div(&half_turn(from), digits)
+}
+
+// Whether x is an odd (or even) multiple of pi/2 to within the given significant digits, as
+// x = pi/2 itself is rounded and never quite reaches the pole of tan
+// Reducing an angle takes pi to the working digits plus the digits of its integer part, so
+// trig functions refuse angles with more than MAX_PRECISION digits before the point
+pub(super) fn is_reducible(x: &Decimal) -> bool {
+    x.top() < MAX_PRECISION as i64
+}
+
+pub(super) fn is_half_pi_multiple(x: &Decimal, odd: bool, digits: usize) -> bool {
+    let working = digits + GUARD_DIGITS;
+    let half_pi = pi(working).div(&Decimal::from_integer(2), working);
+    let k = x.div(&half_pi, working).round_whole();
+    let Some(n) = k.to_integer() else {
+        return false;
+    };
+    let tolerance = Decimal::new(n.abs(), 1 - digits as i64);
+    n.is_odd() == odd && x.sub(&k.mul(&half_pi, working), working).abs() <= tolerance
+}
+
+// Decimal versions of the operators, rounded to the given significant digits. None means a
+// domain error (or a result out of range), and the caller falls back to floating point.
+pub(super) fn unary(
+    operation: &UnaryOperator,
+    operand: &Decimal,
+    digits: usize,
+) -> Option<Decimal> {
+    match operation {
+        UnaryOperator::Factorial => factorial(operand, digits),
+        // Only whole numbers, the continuation between them is computed in floating point
+        UnaryOperator::DoubleFactorial => {
+            let n = integer::double_factorial(&operand.to_integer()?)?;
+            Some(Decimal::from_integer(n).round(digits))
+        }
+        UnaryOperator::SquareRoot if operand.is_negative() => None,
+        UnaryOperator::SquareRoot => Some(operand.sqrt(digits)),
+        UnaryOperator::Sin => operand.sin(digits),
+        UnaryOperator::Cos => operand.cos(digits),
+        UnaryOperator::Tan => operand.tan(digits),
+        UnaryOperator::Ln => operand.ln(digits),
+        UnaryOperator::Floor => Some(operand.floor()),
+        UnaryOperator::Ceil => Some(operand.ceil()),
+        UnaryOperator::Abs => Some(operand.abs()),
+        UnaryOperator::Round => Some(operand.round_whole()),
+        UnaryOperator::Negate => Some(operand.neg()),
+        UnaryOperator::Not => Some(truth(operand.is_zero())),
+        UnaryOperator::Gamma => operand.gamma(digits),
+        UnaryOperator::LogGamma => operand.ln_gamma(digits),
+        UnaryOperator::Digamma => operand.digamma(digits),
+        UnaryOperator::Real | UnaryOperator::Conjugate => Some(operand.clone()),
+        UnaryOperator::Imaginary => Some(Decimal::zero()),
+        UnaryOperator::Argument if operand.is_negative() => Some(pi(digits)),
+        UnaryOperator::Argument => Some(Decimal::zero()),
+        UnaryOperator::Asin => operand.asin(digits),
+        UnaryOperator::Acos => operand.acos(digits),
+        UnaryOperator::Atan => Some(operand.atan(digits)),
+        UnaryOperator::Sec => operand.cos(digits + GUARD_DIGITS)?.reciprocal(digits),
+        UnaryOperator::Csc => operand.sin(digits + GUARD_DIGITS)?.reciprocal(digits),
+        UnaryOperator::Cot => {
+            let working = digits + GUARD_DIGITS;
+            let cos = operand.cos(working)?;
+            Some(cos.mul(&operand.sin(working)?.reciprocal(working)?, digits))
+        }
+        UnaryOperator::Sinh => operand.sinh(digits),
+        UnaryOperator::Cosh => operand.cosh(digits),
+        UnaryOperator::Tanh => operand.tanh(digits),
+        UnaryOperator::Asinh => operand.asinh(digits),
+        UnaryOperator::Acosh => operand.acosh(digits),
+        UnaryOperator::Atanh => operand.atanh(digits),
+        UnaryOperator::Angle(_) => unreachable!(),
+    }
+}
+
+pub(super) fn binary(
+    operation: &BinaryOperator,
+    left: &Decimal,
+    right: &Decimal,
+    digits: usize,
+) -> Option<Decimal> {
+    match operation {
+        BinaryOperator::Add => Some(left.add(right, digits)),
+        BinaryOperator::Subtract => Some(left.sub(right, digits)),
+        BinaryOperator::Multiply => Some(left.mul(right, digits)),
+        BinaryOperator::Divide | BinaryOperator::Modulo if right.is_zero() => None,
+        BinaryOperator::Divide => Some(left.div(right, digits)),
+        BinaryOperator::Modulo => Some(left.rem(right).round(digits)),
+        BinaryOperator::Power => left.powf(right, digits),
+        BinaryOperator::Log => logarithm(left, right, digits),
+        BinaryOperator::Equal => Some(truth(left == right)),
+        BinaryOperator::NotEqual => Some(truth(left != right)),
+        BinaryOperator::Less => Some(truth(left < right)),
+        BinaryOperator::LessEqual => Some(truth(left <= right)),
+        BinaryOperator::Greater => Some(truth(left > right)),
+        BinaryOperator::GreaterEqual => Some(truth(left >= right)),
+        BinaryOperator::And | BinaryOperator::Or => Some(truth(!right.is_zero())),
+        BinaryOperator::MatrixMultiply => unreachable!(),
+    }
+}
+
+// Functions with an argument list, except max, min and if, which don't depend on the number type
+pub(super) fn call(function: &Function, arguments: &[Decimal], digits: usize) -> Option<Decimal> {
+    match function {
+        Function::Log => match arguments.get(1) {
+            Some(base) => logarithm(&arguments[0], base, digits),
+            None => logarithm(&arguments[0], &Decimal::from_integer(10), digits),
+        },
+        Function::Atan2 => Some(atan2(&arguments[0], &arguments[1], digits)),
+        Function::Hypot => {
+            let working = digits + GUARD_DIGITS;
+            let (x, y) = (&arguments[0], &arguments[1]);
+            let sum = x.mul(x, working).add(&y.mul(y, working), working);
+            Some(sum.sqrt(digits))
+        }
+        Function::Beta => beta(&arguments[0], &arguments[1], digits),
+        _ => None,
+    }
+}
+
+// Γ(x + 1) for non-integers and whole numbers beyond MAX_PRODUCT. Callers must rule out
+// negative integers.
+fn factorial(operand: &Decimal, digits: usize) -> Option<Decimal> {
+    let Some(n) = operand
+        .to_integer()
+        .and_then(|n| n.to_u64())
+        .filter(|&n| n < MAX_PRODUCT)
+    else {
+        return operand
+            .add(&Decimal::from_integer(1), digits + GUARD_DIGITS)
+            .gamma(digits);
+    };
+    let working = digits + GUARD_DIGITS;
+    let mut product = Decimal::from_integer(1);
+    for k in 2..=n {
+        product = product.mul(&Decimal::from_integer(k), working);
+    }
+    Some(product.round(digits))
+}
+
+// B(a, b) = Γ(a) Γ(b) / Γ(a + b). Callers must rule out poles of Γ(a) and Γ(b).
+fn beta(a: &Decimal, b: &Decimal, digits: usize) -> Option<Decimal> {
+    let working = digits + GUARD_DIGITS;
+    let sum = a.add(b, working);
+    if sum.to_integer().is_some_and(|n| !n.is_positive()) {
+        return Some(Decimal::zero()); // Γ(a + b) is infinite
+    }
+    let numerator = a.gamma(working)?.mul(&b.gamma(working)?, working);
+    Some(numerator.div(&sum.gamma(working)?, digits))
+}
+
+// Appends the next even Bernoulli number to B_0, B_1, ... (and the zero odd one before it),
+// using the recurrence sum C(m + 1, j) B_j = 0 for j = 0..=m
+fn next_bernoulli(numbers: &mut Vec<BigRational>) -> BigRational {
+    if numbers.is_empty() {
+        numbers.push(BigRational::from_integer(1.into()));
+        numbers.push(BigRational::new((-1).into(), 2.into()));
+    } else {
+        numbers.push(BigRational::zero());
+    }
+
+    let m = numbers.len();
+    let mut binomial = BigInt::from(1);
+    let mut sum = BigRational::zero();
+    for (j, b) in numbers.iter().enumerate() {
+        sum += b * BigRational::from_integer(binomial.clone());
+        binomial = binomial * (m + 1 - j) / (j + 1);
+    }
+    let next = -sum / BigRational::from_integer((m + 1).into());
+    numbers.push(next.clone());
+    next
+}
+
+fn logarithm(value: &Decimal, base: &Decimal, digits: usize) -> Option<Decimal> {
+    if base == &Decimal::from_integer(1) {
+        return None;
+    }
+    let working = digits + GUARD_DIGITS;
+    Some(value.ln(working)?.div(&base.ln(working)?, digits))
+}
+
+// Angle of (x, y) in radians, in (-pi, pi]
+fn atan2(y: &Decimal, x: &Decimal, digits: usize) -> Decimal {
+    let working = digits + GUARD_DIGITS;
+    if x.is_zero() {
+        let half_pi = pi(digits).div(&Decimal::from_integer(2), digits);
+        return match y.mantissa.sign() {
+            Sign::Plus => half_pi,
+            Sign::Minus => half_pi.neg(),
+            Sign::NoSign => Decimal::zero(),
+        };
+    }
+
+    let angle = y.div(x, working).atan(working);
+    let result = match (x.is_negative(), y.is_negative()) {
+        (false, _) => angle,
+        (true, false) => angle.add(&pi(working), working),
+        (true, true) => angle.sub(&pi(working), working),
+    };
+    result.round(digits)
+}
+
+fn truth(condition: bool) -> Decimal {
+    Decimal::from_integer(condition as u8)
+}
+
+// The mantissa of d scaled to a smaller exponent
+fn align(d: &Decimal, exponent: i64) -> BigInt {
+    &d.mantissa * power_of_ten((d.exponent - exponent) as usize)
+}
+
+fn power_of_ten(n: usize) -> BigInt {
+    BigInt::from(10).pow(n as u32)
+}
+
+fn digit_count(n: &BigInt) -> usize {
+    if n.is_zero() {
+        1
+    } else {
+        n.magnitude().to_string().len()
+    }
+}
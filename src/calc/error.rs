@@ -0,0 +1,239 @@
+use std::fmt;
+use std::ops::Range;
+
+// Byte range of the offending input, used to underline it in diagnostics
+pub type Span = Range<usize>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedChar {
+        found: char,
+        span: Span,
+    },
+    UnexpectedEnd {
+        span: Span,
+    },
+    UnbalancedParen {
+        span: Span,
+    },
+    UnbalancedBracket {
+        span: Span,
+    },
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+    WrongArgumentCount {
+        name: String,
+        expected: String,
+        found: usize,
+        span: Span,
+    },
+    TrailingInput {
+        span: Span,
+    },
+    BadNumber {
+        text: String,
+        span: Span,
+    },
+    InvalidDigit {
+        digit: char,
+        radix: u32,
+        span: Span,
+    },
+    ReservedName {
+        name: String,
+        span: Span,
+    },
+    DuplicateParameter {
+        name: String,
+        span: Span,
+    },
+    IndexNotName {
+        name: String,
+        span: Span,
+    },
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedChar { span, .. }
+            | ParseError::UnexpectedEnd { span }
+            | ParseError::UnbalancedParen { span }
+            | ParseError::UnbalancedBracket { span }
+            | ParseError::UnknownFunction { span, .. }
+            | ParseError::WrongArgumentCount { span, .. }
+            | ParseError::TrailingInput { span }
+            | ParseError::BadNumber { span, .. }
+            | ParseError::InvalidDigit { span, .. }
+            | ParseError::ReservedName { span, .. }
+            | ParseError::DuplicateParameter { span, .. }
+            | ParseError::IndexNotName { span, .. } => span.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedChar { found, .. } => {
+                write!(f, "Unexpected character: '{}'", found)
+            }
+            ParseError::UnexpectedEnd { .. } => write!(f, "Unexpected end of input"),
+            ParseError::UnbalancedParen { .. } => write!(f, "Unbalanced parenthesis"),
+            ParseError::UnbalancedBracket { .. } => write!(f, "Unbalanced bracket"),
+            ParseError::UnknownFunction { name, .. } => write!(f, "Unknown function: {}", name),
+            ParseError::WrongArgumentCount {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Function '{}' expects {} argument(s), got {}",
+                name, expected, found
+            ),
+            ParseError::TrailingInput { .. } => write!(f, "Unexpected characters at end of input"),
+            ParseError::BadNumber { text, .. } => write!(f, "Invalid number: {}", text),
+            ParseError::InvalidDigit { digit, radix, .. } => {
+                write!(f, "Invalid digit '{}' for base {} number", digit, radix)
+            }
+            ParseError::ReservedName { name, .. } => {
+                write!(f, "Cannot assign to built-in name: {}", name)
+            }
+            ParseError::DuplicateParameter { name, .. } => {
+                write!(f, "Duplicate parameter: {}", name)
+            }
+            ParseError::IndexNotName { name, .. } => {
+                write!(f, "The index of '{}' must be a name", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Errors returned by Calculator::eval and Calculator::eval_all
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Syntax(ParseError),      // The input could not be parsed
+    Math(String),            // A calculation has no result, like 1 / 0, and why
+    NoResult,                // Calculator::eval got only definitions
+    UnknownVariable(String), // A variable was used before being assigned
+    UnknownFunction(String), // A user function was removed after being used in a definition
+    WrongArgumentCount {
+        // A user function was redefined with a different number of parameters
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    RecursionLimit, // User functions nested too deeply
+    IterationLimit, // A sum or product over too many terms
+    Domain(String), // A function was used where it is undefined, like gamma(0)
+    IntegerRequired {
+        // A function that only takes whole numbers, like gcd, got something else
+        name: String,
+        found: String,
+    },
+    ListRequired {
+        // A list function like sort, or indexing, got something else
+        name: String,
+        found: String,
+    },
+    LengthMismatch {
+        // Two lists combined element by element have different lengths
+        left: usize,
+        right: usize,
+    },
+    IndexOutOfRange {
+        index: String,
+        length: usize,
+    },
+    MatrixRequired {
+        // A matrix function got something other than a list of rows of equal length
+        name: String,
+        found: String,
+    },
+    SquareRequired {
+        // A function like det or inv got a matrix that isn't square, shown as rows x columns
+        name: String,
+        found: String,
+    },
+    DimensionMismatch {
+        // Matrices multiplied with @ or passed to linsolve have incompatible shapes
+        operation: String,
+        left: String,
+        right: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax(e) => write!(f, "Syntax Error: {}", e),
+            Error::Math(reason) => write!(f, "Mathematical error: {}", reason),
+            Error::NoResult => write!(f, "Input has no result"),
+            Error::UnknownVariable(name) => write!(f, "Unknown variable: {}", name),
+            Error::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            Error::WrongArgumentCount {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Function '{}' expects {} argument(s), got {}",
+                name, expected, found
+            ),
+            Error::RecursionLimit => write!(f, "Maximum function call depth exceeded"),
+            Error::IterationLimit => write!(f, "Maximum number of terms in sum or prod exceeded"),
+            Error::Domain(message) => write!(f, "Domain error: {}", message),
+            Error::IntegerRequired { name, found } => {
+                write!(
+                    f,
+                    "Function '{}' expects whole numbers, got {}",
+                    name, found
+                )
+            }
+            Error::ListRequired { name, found } => {
+                write!(f, "'{}' expects a list, got {}", name, found)
+            }
+            Error::LengthMismatch { left, right } => {
+                write!(f, "Lists have different lengths: {} and {}", left, right)
+            }
+            Error::IndexOutOfRange { index, length } => write!(
+                f,
+                "Index {} is out of range for a list of length {}",
+                index, length
+            ),
+            Error::MatrixRequired { name, found } => {
+                write!(f, "'{}' expects a matrix, got {}", name, found)
+            }
+            Error::SquareRequired { name, found } => {
+                write!(f, "'{}' expects a square matrix, got {}", name, found)
+            }
+            Error::DimensionMismatch {
+                operation,
+                left,
+                right,
+            } => write!(
+                f,
+                "Matrix dimensions don't match for '{}': {} and {}",
+                operation, left, right
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Syntax(e)
+    }
+}
+
+// Error::Math for a calculation that has no result, like a division by zero
+pub(super) fn math_error(reason: &str) -> Error {
+    Error::Math(reason.to_string())
+}
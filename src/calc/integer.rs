@@ -0,0 +1,138 @@
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::calc::parser::{BinaryOperator, UnaryOperator};
+
+// Largest exact result of a power or factorial, in bits. The calculator refuses larger ones
+// up front, the checks here only keep the work bounded.
+pub(super) const MAX_BITS: u64 = 1 << 20;
+
+// Exact versions of the operators for whole numbers. None means the result is not a whole
+// number (or is a domain error or too large), and the caller falls back to floating point.
+pub(super) fn unary(operation: &UnaryOperator, operand: &BigInt) -> Option<BigInt> {
+    match operation {
+        UnaryOperator::Factorial => factorial(operand),
+        UnaryOperator::DoubleFactorial => double_factorial(operand),
+        UnaryOperator::SquareRoot => square_root(operand),
+        UnaryOperator::Floor | UnaryOperator::Ceil | UnaryOperator::Round => Some(operand.clone()),
+        UnaryOperator::Abs => Some(operand.abs()),
+        UnaryOperator::Negate => Some(-operand),
+        UnaryOperator::Not => Some(truth(operand.is_zero())),
+        UnaryOperator::Gamma => factorial(&(operand - 1)), // Γ(n) = (n - 1)!
+        UnaryOperator::Real | UnaryOperator::Conjugate => Some(operand.clone()),
+        UnaryOperator::Imaginary => Some(BigInt::zero()),
+        UnaryOperator::Argument if !operand.is_negative() => Some(BigInt::zero()),
+        UnaryOperator::Argument => None, // pi
+        UnaryOperator::Angle(_) => unreachable!(),
+        UnaryOperator::Sin
+        | UnaryOperator::Cos
+        | UnaryOperator::Tan
+        | UnaryOperator::Sec
+        | UnaryOperator::Csc
+        | UnaryOperator::Cot
+        | UnaryOperator::Asin
+        | UnaryOperator::Acos
+        | UnaryOperator::Atan
+        | UnaryOperator::Sinh
+        | UnaryOperator::Cosh
+        | UnaryOperator::Tanh
+        | UnaryOperator::Asinh
+        | UnaryOperator::Acosh
+        | UnaryOperator::Atanh
+        | UnaryOperator::Ln
+        | UnaryOperator::LogGamma
+        | UnaryOperator::Digamma => None,
+    }
+}
+
+pub(super) fn binary(operation: &BinaryOperator, left: &BigInt, right: &BigInt) -> Option<BigInt> {
+    match operation {
+        BinaryOperator::Add => Some(left + right),
+        BinaryOperator::Subtract => Some(left - right),
+        BinaryOperator::Multiply => Some(left * right),
+        BinaryOperator::Divide | BinaryOperator::Modulo if right.is_zero() => None,
+        // Only exact quotients stay whole, 7 / 2 is 3.5
+        BinaryOperator::Divide => {
+            let (quotient, remainder) = left.div_rem(right);
+            remainder.is_zero().then_some(quotient)
+        }
+        BinaryOperator::Modulo => Some(left % right), // Sign follows the dividend, like f64 %
+        BinaryOperator::Power => power(left, right),
+        BinaryOperator::Log => None,
+        BinaryOperator::Equal => Some(truth(left == right)),
+        BinaryOperator::NotEqual => Some(truth(left != right)),
+        BinaryOperator::Less => Some(truth(left < right)),
+        BinaryOperator::LessEqual => Some(truth(left <= right)),
+        BinaryOperator::Greater => Some(truth(left > right)),
+        BinaryOperator::GreaterEqual => Some(truth(left >= right)),
+        BinaryOperator::And | BinaryOperator::Or => Some(truth(!right.is_zero())),
+        BinaryOperator::MatrixMultiply => unreachable!(),
+    }
+}
+
+// Non-negative powers, as long as the result stays a reasonable size
+pub(super) fn power(base: &BigInt, exponent: &BigInt) -> Option<BigInt> {
+    let exponent = exponent.to_u32()?;
+    if log2(base) * exponent as f64 > MAX_BITS as f64 {
+        return None;
+    }
+    Some(base.pow(exponent))
+}
+
+// log2 |n|, also beyond the range of f64
+pub(super) fn log2(n: &BigInt) -> f64 {
+    let shift = n.bits().saturating_sub(64);
+    (n.abs() >> shift).to_f64().unwrap_or_default().log2() + shift as f64
+}
+
+pub(super) fn factorial(n: &BigInt) -> Option<BigInt> {
+    let n = n.to_u64()?;
+    // Stirling's approximation of log2(n!) rules out huge results before computing them
+    let x = n.max(1) as f64;
+    let bits = (x * x.ln() - x + (2.0 * std::f64::consts::PI * x).ln() / 2.0) / 2f64.ln();
+    if bits > MAX_BITS as f64 {
+        return None;
+    }
+    Some(product(1, n))
+}
+
+// n!! = n (n - 2) (n - 4) ... down to 1 or 2, with (-1)!! = 1 as the only negative whole result
+pub(super) fn double_factorial(n: &BigInt) -> Option<BigInt> {
+    if *n == BigInt::from(-1) {
+        return Some(BigInt::from(1));
+    }
+    let n = n.to_u64()?;
+    let half = n / 2;
+    let half_factorial = factorial(&half.into())? << half; // 2^k k!
+    if n.is_even() {
+        Some(half_factorial) // (2k)!! = 2^k k!
+    } else {
+        Some(factorial(&n.into())? / half_factorial) // (2k + 1)!! = (2k + 1)! / (2^k k!)
+    }
+}
+
+// Product of low..=high, split in halves so the multiplications stay balanced
+pub(super) fn product(low: u64, high: u64) -> BigInt {
+    match high.saturating_sub(low) {
+        _ if low > high => BigInt::from(1),
+        0..=8 => (low..=high).map(BigInt::from).product(),
+        _ => {
+            let middle = low + (high - low) / 2;
+            product(low, middle) * product(middle + 1, high)
+        }
+    }
+}
+
+// Square roots of perfect squares
+pub(super) fn square_root(n: &BigInt) -> Option<BigInt> {
+    if n.is_negative() {
+        return None;
+    }
+    let root = n.sqrt();
+    (&root * &root == *n).then_some(root)
+}
+
+pub(super) fn truth(condition: bool) -> BigInt {
+    BigInt::from(condition as u8)
+}
@@ -0,0 +1,92 @@
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+
+// Linear algebra on exact fractions. Gauss-Jordan elimination never loses a pivot to
+// rounding, so singular matrices are recognised exactly and the results are rounded once at
+// the end. Matrices are lists of rows, and callers check their dimensions.
+
+pub(super) fn determinant(matrix: &[Vec<BigRational>]) -> BigRational {
+    let mut rows = matrix.to_vec();
+    eliminate(&mut rows, matrix.len()).1
+}
+
+pub(super) fn rank(matrix: &[Vec<BigRational>]) -> usize {
+    let mut rows = matrix.to_vec();
+    eliminate(&mut rows, matrix[0].len()).0
+}
+
+// None for a singular matrix
+pub(super) fn inverse(matrix: &[Vec<BigRational>]) -> Option<Vec<Vec<BigRational>>> {
+    solve(matrix, &identity(matrix.len()))
+}
+
+// The X with A X = B for a square A, with a column of X for each column of B, or None when A is
+// singular
+pub(super) fn solve(
+    a: &[Vec<BigRational>],
+    b: &[Vec<BigRational>],
+) -> Option<Vec<Vec<BigRational>>> {
+    let n = a.len();
+    let mut rows: Vec<Vec<BigRational>> = a
+        .iter()
+        .zip(b)
+        .map(|(left, right)| left.iter().chain(right).cloned().collect())
+        .collect();
+    if eliminate(&mut rows, n).0 < n {
+        return None;
+    }
+    // A is now the identity, which leaves X where B was
+    Some(rows.into_iter().map(|row| row[n..].to_vec()).collect())
+}
+
+pub(super) fn identity(n: usize) -> Vec<Vec<BigRational>> {
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    if i == j {
+                        BigRational::one()
+                    } else {
+                        BigRational::zero()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Reduces the first `columns` columns to reduced row echelon form, applying the same row
+// operations to any columns after them. Returns the rank of those columns and, when they are
+// square, their determinant.
+fn eliminate(rows: &mut [Vec<BigRational>], columns: usize) -> (usize, BigRational) {
+    let mut rank = 0;
+    let mut determinant = BigRational::one();
+    for column in 0..columns {
+        let Some(pivot) = (rank..rows.len()).find(|&row| !rows[row][column].is_zero()) else {
+            determinant = BigRational::zero();
+            continue;
+        };
+        if pivot != rank {
+            rows.swap(pivot, rank);
+            determinant = -determinant;
+        }
+        let scale = rows[rank][column].clone();
+        determinant *= &scale;
+        for value in &mut rows[rank] {
+            *value /= &scale;
+        }
+
+        let pivot_row = rows[rank].clone();
+        for (i, row) in rows.iter_mut().enumerate() {
+            if i == rank || row[column].is_zero() {
+                continue;
+            }
+            let factor = row[column].clone();
+            for (value, pivot_value) in row.iter_mut().zip(&pivot_row) {
+                *value -= &factor * pivot_value;
+            }
+        }
+        rank += 1;
+    }
+    (rank, determinant)
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+
+use crate::calc::combinatorics;
+use crate::calc::error::Error;
+use crate::calc::parser::Function;
+use crate::calc::value::Value;
+
+// Primes below this are found by trial division before trying Pollard's rho
+const TRIAL_DIVISION_LIMIT: u32 = 10_000;
+// Largest number left after trial division that is tested for primality and split further
+const MAX_FACTOR_BITS: u64 = 1024;
+// Steps of Pollard's rho for a whole factorisation before giving up, each counted once per 64
+// bits of the number being split, which takes about a second
+const MAX_RHO_WORK: u64 = 1 << 19;
+// Miller-Rabin witnesses; the first 13 primes are exact below 3.3 * 10^24
+const WITNESSES: [u32; 20] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71,
+];
+
+/// Prime factorisation of a whole number, produced by `factor`. It is shown as a product
+/// like `2^3 * 3^2 * 5` and otherwise behaves like the number itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Factors {
+    negative: bool,
+    primes: Vec<(BigInt, u32)>, // Ascending primes and their exponents
+}
+
+impl Factors {
+    /// The primes in ascending order, each with its exponent.
+    pub fn primes(&self) -> &[(BigInt, u32)] {
+        &self.primes
+    }
+
+    /// The number that was factored.
+    pub fn value(&self) -> BigInt {
+        let product: BigInt = self
+            .primes
+            .iter()
+            .map(|(prime, exponent)| prime.pow(*exponent))
+            .product();
+        if self.negative { -product } else { product }
+    }
+}
+
+impl fmt::Display for Factors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negative {
+            write!(f, "-")?;
+        }
+        if self.primes.is_empty() {
+            return write!(f, "1");
+        }
+        for (i, (prime, exponent)) in self.primes.iter().enumerate() {
+            if i > 0 {
+                write!(f, " * ")?;
+            }
+            match exponent {
+                1 => write!(f, "{}", prime)?,
+                _ => write!(f, "{}^{}", prime, exponent)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Number theory and combinatorics functions, which only take whole numbers
+pub(super) fn call(function: &Function, arguments: &[Value]) -> Result<Value, Error> {
+    let name = match function {
+        Function::Gcd => "gcd",
+        Function::Lcm => "lcm",
+        Function::IsPrime => "isprime",
+        Function::Factor => "factor",
+        Function::NextPrime => "nextprime",
+        Function::Totient => "totient",
+        Function::ModPow => "modpow",
+        Function::ModInverse => "modinv",
+        Function::Choose => "ncr",
+        Function::Permutations => "npr",
+        Function::Multinomial => "multinomial",
+        Function::Subfactorial => "subfactorial",
+        _ => unreachable!(),
+    };
+    let arguments: Vec<BigInt> = arguments
+        .iter()
+        .map(|argument| {
+            argument.to_integer().ok_or_else(|| Error::IntegerRequired {
+                name: name.to_string(),
+                found: argument.to_string(),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let domain = |message: String| Error::Domain(format!("{name}: {message}"));
+
+    let result = match function {
+        Function::Gcd => arguments.iter().fold(BigInt::zero(), |a, b| a.gcd(b)),
+        Function::Lcm => arguments.iter().fold(BigInt::one(), |a, b| a.lcm(b)),
+        Function::IsPrime => BigInt::from(is_prime(&arguments[0]) as u8),
+        Function::Factor => {
+            let factors = factor(&arguments[0]).map_err(domain)?;
+            return Ok(Value::Factors(factors));
+        }
+        Function::NextPrime => next_prime(&arguments[0]),
+        Function::Totient => totient(&arguments[0]).map_err(domain)?,
+        Function::ModPow => modpow(&arguments[0], &arguments[1], &arguments[2]).map_err(domain)?,
+        Function::ModInverse => modinv(&arguments[0], &arguments[1]).map_err(domain)?,
+        Function::Choose => combinatorics::choose(&arguments[0], &arguments[1]).map_err(domain)?,
+        Function::Permutations => {
+            combinatorics::permutations(&arguments[0], &arguments[1]).map_err(domain)?
+        }
+        Function::Multinomial => combinatorics::multinomial(&arguments).map_err(domain)?,
+        Function::Subfactorial => combinatorics::subfactorial(&arguments[0]).map_err(domain)?,
+        _ => unreachable!(),
+    };
+    Ok(Value::Integer(result))
+}
+
+// Miller-Rabin with fixed witnesses: exact below 3.3 * 10^24, and wrong above that
+// with a probability below 4^-20
+pub(super) fn is_prime(n: &BigInt) -> bool {
+    if *n < BigInt::from(2) {
+        return false;
+    }
+    for witness in WITNESSES {
+        let witness = BigInt::from(witness);
+        if *n == witness {
+            return true;
+        }
+        if (n % &witness).is_zero() {
+            return false;
+        }
+    }
+
+    // n - 1 = d * 2^s with d odd
+    let n_minus_one = n - BigInt::one();
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+    'witnesses: for witness in WITNESSES {
+        let mut x = BigInt::from(witness).modpow(&d, n);
+        if x.is_one() || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = &x * &x % n;
+            if x == n_minus_one {
+                continue 'witnesses;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+fn next_prime(n: &BigInt) -> BigInt {
+    let mut candidate = n + BigInt::one();
+    if candidate <= BigInt::from(2) {
+        return BigInt::from(2);
+    }
+    if candidate.is_even() {
+        candidate += 1;
+    }
+    while !is_prime(&candidate) {
+        candidate += 2;
+    }
+    candidate
+}
+
+fn factor(n: &BigInt) -> Result<Factors, String> {
+    if n.is_zero() {
+        return Err("0 has no prime factorisation".to_string());
+    }
+    let mut primes = Vec::new();
+    let mut rest = n.abs();
+
+    let mut divisor = 2u32;
+    while divisor < TRIAL_DIVISION_LIMIT && BigInt::from(divisor) * divisor <= rest {
+        let mut exponent = 0;
+        while (&rest % divisor).is_zero() {
+            rest /= divisor;
+            exponent += 1;
+        }
+        if exponent > 0 {
+            primes.push((BigInt::from(divisor), exponent));
+        }
+        divisor += if divisor == 2 { 1 } else { 2 };
+    }
+
+    // What is left has only large prime factors, split apart with Pollard's rho
+    let mut pending = vec![rest];
+    let mut large = Vec::new();
+    let mut work = 0;
+    let too_large = || "number is too large to factor".to_string();
+    while let Some(m) = pending.pop() {
+        if m.is_one() {
+            continue;
+        }
+        if m.bits() > MAX_FACTOR_BITS {
+            return Err(too_large());
+        }
+        if is_prime(&m) {
+            large.push(m);
+            continue;
+        }
+        let found = pollard_rho(&m, &mut work).ok_or_else(too_large)?;
+        pending.push(&m / &found);
+        pending.push(found);
+    }
+    large.sort();
+    for prime in large {
+        match primes.last_mut() {
+            Some((last, exponent)) if *last == prime => *exponent += 1,
+            _ => primes.push((prime, 1)),
+        }
+    }
+
+    Ok(Factors {
+        negative: n.is_negative(),
+        primes,
+    })
+}
+
+// Brent's variant of Pollard's rho: a non-trivial factor of the composite n, if one turns up
+// before the work shared by all factors of a number reaches MAX_RHO_WORK
+fn pollard_rho(n: &BigInt, work: &mut u64) -> Option<BigInt> {
+    let step_work = n.bits().div_ceil(64);
+    for c in 1u32.. {
+        let f = |x: &BigInt| (x * x + c) % n;
+        let (mut x, mut y) = (BigInt::from(2), BigInt::from(2));
+        let mut power = 1u64;
+        let mut length = 1u64;
+        loop {
+            if power == length {
+                x = y.clone();
+                power *= 2;
+                length = 0;
+            }
+            y = f(&y);
+            length += 1;
+            *work += step_work;
+            if *work > MAX_RHO_WORK {
+                return None;
+            }
+            let d = (&x - &y).abs().gcd(n);
+            if d == *n {
+                break; // Cycle without a factor, try another polynomial
+            }
+            if !d.is_one() {
+                return Some(d);
+            }
+        }
+    }
+    None
+}
+
+// Count of 1..=n coprime to n: n * (1 - 1/p) over its prime factors p
+fn totient(n: &BigInt) -> Result<BigInt, String> {
+    if !n.is_positive() {
+        return Err(format!("{n} is not positive"));
+    }
+    let factors = factor(n)?;
+    Ok(factors
+        .primes
+        .iter()
+        .fold(n.clone(), |result, (prime, _)| result / prime * (prime - 1)))
+}
+
+// base^exponent mod modulus, in 0..|modulus|; negative exponents use the modular inverse
+fn modpow(base: &BigInt, exponent: &BigInt, modulus: &BigInt) -> Result<BigInt, String> {
+    if modulus.is_zero() {
+        return Err("modulus must not be 0".to_string());
+    }
+    let modulus = modulus.abs();
+    let base = if exponent.is_negative() {
+        modinv(base, &modulus)?
+    } else {
+        base.mod_floor(&modulus)
+    };
+    Ok(base.modpow(&exponent.abs(), &modulus))
+}
+
+// x with a * x = 1 mod modulus, in 0..|modulus|
+fn modinv(a: &BigInt, modulus: &BigInt) -> Result<BigInt, String> {
+    if modulus.is_zero() {
+        return Err("modulus must not be 0".to_string());
+    }
+    let modulus = modulus.abs();
+    let extended = a.mod_floor(&modulus).extended_gcd(&modulus);
+    if !extended.gcd.is_one() {
+        return Err(format!("{a} has no inverse modulo {modulus}"));
+    }
+    Ok(extended.x.mod_floor(&modulus))
+}
@@ -1,93 +1,437 @@
-pub(super) enum Token {
-    Unary(UnaryToken),   // Unary operations like sin, sqrt, !
-    Binary(BinaryToken), // Binary operations like +, -, *, /
-    Value(f64),          // Literal numbers
-    LastResult,          // Reference to the last computed result
-    Constant(Constant),  // Mathematical constants like pi, e
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Pow, Zero};
+
+use crate::calc::error::{ParseError, Span};
+use crate::calc::settings::AngleUnit;
+
+// Largest decimal exponent accepted in literals, so 1e999999999 can't exhaust memory
+const MAX_LITERAL_EXPONENT: u64 = 100_000;
+
+// Units written right after a number, like 90deg. They are reserved, so a variable can't change
+// what 3deg means.
+const ANGLE_SUFFIXES: [(&str, AngleUnit); 3] = [
+    ("deg", AngleUnit::Degrees),
+    ("rad", AngleUnit::Radians),
+    ("grad", AngleUnit::Gradians),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Unary(UnaryToken),           // Unary operations like sin, sqrt, !
+    Binary(BinaryToken),         // Binary operations like +, -, *, /
+    Call(CallToken),             // Function calls with an argument list like log(100, 10)
+    Value(BigRational),          // Literal numbers, kept exact until evaluated
+    LastResult,                  // Reference to the last computed result
+    Constant(Constant),          // Mathematical constants like pi, e
+    Variable(String),            // User variables like rate
+    Assignment(AssignmentToken), // Statements like rate = 0.07
+    Definition(DefinitionToken), // Statements like f(x) = x^2 + 1
+    UserCall(UserCallToken),     // Calls to user-defined functions like f(2)
+    List(Vec<Token>),            // List literals like [3, 1, 4]
+    Index(IndexToken),           // Elements of a list like xs[1]
+    Series(SeriesToken),         // Sums and products over a range like sum(k, 1, 100, k^2)
 }
 
-pub(super) enum Constant {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
     Pi,
     E,
+    I, // The imaginary unit, a name like any other unless complex numbers are enabled
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnaryToken {
+    pub operation: UnaryOperator,
+    pub operand: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryToken {
+    pub left: Box<Token>,
+    pub operation: BinaryOperator,
+    pub right: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssignmentToken {
+    pub name: String,
+    pub value: Box<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefinitionToken {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Box<Token>,
+    pub source: String, // the definition as typed, for listing
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserCallToken {
+    pub name: String,
+    pub arguments: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexToken {
+    pub list: Box<Token>,
+    pub index: Box<Token>, // Counting from 1, or from the end when negative
 }
 
-pub(super) struct UnaryToken {
-    pub(super) operation: UnaryOperator,
-    pub(super) operand: Box<Token>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesToken {
+    pub operation: BinaryOperator, // Add for sum, Multiply for prod
+    pub variable: String,          // Bound to each whole number from `from` to `to` in `body`
+    pub from: Box<Token>,
+    pub to: Box<Token>,
+    pub body: Box<Token>,
 }
 
-pub(super) struct BinaryToken {
-    pub(super) left: Box<Token>,
-    pub(super) operation: BinaryOperator,
-    pub(super) right: Box<Token>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallToken {
+    pub function: Function,
+    pub arguments: Vec<Token>,
 }
 
-pub(super) enum UnaryOperator {
-    Factorial,  // !
-    SquareRoot, // sqrt
-    Sin,        // sin
-    Cos,        // cos
-    Tan,        // tan
-    Ln,         // ln
-    Floor,      // floor
-    Ceil,       // ceil
-    Abs,        // abs
-    Round,      // round
-    Negate,     // unary minus
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryOperator {
+    Factorial,        // !
+    DoubleFactorial,  // !!
+    SquareRoot,       // sqrt
+    Sin,              // sin
+    Cos,              // cos
+    Tan,              // tan
+    Sec,              // sec
+    Csc,              // csc
+    Cot,              // cot
+    Asin,             // asin
+    Acos,             // acos
+    Atan,             // atan
+    Sinh,             // sinh
+    Cosh,             // cosh
+    Tanh,             // tanh
+    Asinh,            // asinh
+    Acosh,            // acosh
+    Atanh,            // atanh
+    Ln,               // ln
+    Floor,            // floor
+    Ceil,             // ceil
+    Abs,              // abs
+    Round,            // round
+    Negate,           // unary minus
+    Not,              // prefix ! (logical not)
+    Gamma,            // gamma
+    LogGamma,         // lgamma
+    Digamma,          // digamma
+    Real,             // re
+    Imaginary,        // im
+    Argument,         // arg
+    Conjugate,        // conj
+    Angle(AngleUnit), // ° deg rad grad after a number, converted to the angle unit setting
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryOperator {
+    Add,            // +
+    Subtract,       // -
+    Multiply,       // *
+    Divide,         // /
+    Power,          // ^
+    Modulo,         // %
+    Log,            // log
+    MatrixMultiply, // @
+    Equal,          // ==
+    NotEqual,       // != ≠
+    Less,           // <
+    LessEqual,      // <= ≤
+    Greater,        // >
+    GreaterEqual,   // >= ≥
+    And,            // &&
+    Or,             // ||
+}
+
+impl UnaryOperator {
+    // Trig functions, which take an angle in the angle unit setting
+    pub(crate) fn takes_angle(&self) -> bool {
+        matches!(
+            self,
+            UnaryOperator::Sin
+                | UnaryOperator::Cos
+                | UnaryOperator::Tan
+                | UnaryOperator::Sec
+                | UnaryOperator::Csc
+                | UnaryOperator::Cot
+        )
+    }
+
+    // Inverse trig functions, which return an angle in the angle unit setting
+    pub(crate) fn returns_angle(&self) -> bool {
+        matches!(
+            self,
+            UnaryOperator::Asin | UnaryOperator::Acos | UnaryOperator::Atan
+        )
+    }
+}
+
+impl Function {
+    // Number theory and combinatorics functions, which only take whole numbers
+    pub(crate) fn takes_integers(&self) -> bool {
+        matches!(
+            self,
+            Function::Gcd
+                | Function::Lcm
+                | Function::IsPrime
+                | Function::Factor
+                | Function::NextPrime
+                | Function::Totient
+                | Function::ModPow
+                | Function::ModInverse
+                | Function::Choose
+                | Function::Permutations
+                | Function::Multinomial
+                | Function::Subfactorial
+        )
+    }
+
+    // List functions and statistics, which take whole lists instead of applying to each element
+    pub(crate) fn takes_list(&self) -> bool {
+        matches!(
+            self,
+            Function::Length
+                | Function::Concat
+                | Function::Sort
+                | Function::Sum
+                | Function::Product
+                | Function::Mean
+                | Function::Median
+                | Function::Mode
+                | Function::Variance
+                | Function::PopulationVariance
+                | Function::Deviation
+                | Function::PopulationDeviation
+                | Function::Range
+                | Function::Percentile
+                | Function::Quartiles
+                | Function::ZScore
+        )
+    }
+
+    // Matrix functions, which work on whole matrices instead of applying to each element
+    pub(crate) fn takes_matrix(&self) -> bool {
+        matches!(
+            self,
+            Function::Determinant
+                | Function::Inverse
+                | Function::Transpose
+                | Function::Rank
+                | Function::Trace
+                | Function::Identity
+                | Function::LinearSolve
+        )
+    }
+}
+
+impl BinaryOperator {
+    // Comparison and boolean operators, which produce 1 or 0
+    pub(crate) fn is_logical(&self) -> bool {
+        !matches!(
+            self,
+            BinaryOperator::Add
+                | BinaryOperator::Subtract
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::Power
+                | BinaryOperator::Modulo
+                | BinaryOperator::Log
+                | BinaryOperator::MatrixMultiply
+        )
+    }
 }
 
-pub(super) enum BinaryOperator {
-    Add,      // +
-    Subtract, // -
-    Multiply, // *
-    Divide,   // /
-    Power,    // ^
-    Modulo,   // %
-    Log,      // log
+#[derive(Debug, Clone, PartialEq)]
+pub enum Function {
+    Unary(UnaryOperator), // any unary operator called as name(x)
+    Log,                  // log(x, base), base defaults to 10
+    Atan2,                // atan2(y, x)
+    Hypot,                // hypot(x, y)
+    Beta,                 // beta(a, b)
+    Gcd,                  // gcd(a, b, ...)
+    Lcm,                  // lcm(a, b, ...)
+    IsPrime,              // isprime(n)
+    Factor,               // factor(n)
+    NextPrime,            // nextprime(n)
+    Totient,              // totient(n)
+    ModPow,               // modpow(base, exponent, modulus)
+    ModInverse,           // modinv(a, modulus)
+    Choose,               // ncr(n, k)
+    Permutations,         // npr(n, k)
+    Multinomial,          // multinomial(k1, k2, ...)
+    Subfactorial,         // subfactorial(n)
+    Length,               // length(list)
+    Concat,               // concat(a, b, ...)
+    Sort,                 // sort(list)
+    Sum,                  // sum(list)
+    Product,              // prod(list)
+    Mean,                 // mean(list)
+    Median,               // median(list)
+    Mode,                 // mode(list)
+    Variance,             // var(list), of a sample
+    PopulationVariance,   // varp(list)
+    Deviation,            // stdev(list), of a sample
+    PopulationDeviation,  // stdevp(list)
+    Range,                // range(list)
+    Percentile,           // percentile(list, p)
+    Quartiles,            // quartiles(list)
+    ZScore,               // zscore(list) or zscore(x, list)
+    Determinant,          // det(matrix)
+    Inverse,              // inv(matrix)
+    Transpose,            // transpose(matrix)
+    Rank,                 // rank(matrix)
+    Trace,                // trace(matrix)
+    Identity,             // identity(n)
+    LinearSolve,          // linsolve(a, b)
+    Max,                  // max(a, b, ...)
+    Min,                  // min(a, b, ...)
+    If,                   // if(condition, then, else), only the chosen branch is evaluated
 }
 
+impl Function {
+    // Minimum and (optional) maximum number of accepted arguments
+    fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            Function::Unary(_) => (1, Some(1)),
+            Function::Log => (1, Some(2)),
+            Function::Atan2
+            | Function::Hypot
+            | Function::Beta
+            | Function::ModInverse
+            | Function::Choose
+            | Function::Permutations
+            | Function::Percentile
+            | Function::LinearSolve => (2, Some(2)),
+            Function::Gcd | Function::Lcm | Function::Multinomial => (1, None),
+            Function::IsPrime
+            | Function::Factor
+            | Function::NextPrime
+            | Function::Totient
+            | Function::Subfactorial
+            | Function::Length
+            | Function::Sort
+            | Function::Sum
+            | Function::Product
+            | Function::Mean
+            | Function::Median
+            | Function::Mode
+            | Function::Variance
+            | Function::PopulationVariance
+            | Function::Deviation
+            | Function::PopulationDeviation
+            | Function::Range
+            | Function::Quartiles
+            | Function::Determinant
+            | Function::Inverse
+            | Function::Transpose
+            | Function::Rank
+            | Function::Trace
+            | Function::Identity => (1, Some(1)),
+            Function::ZScore => (1, Some(2)),
+            Function::Concat => (1, None),
+            Function::ModPow => (3, Some(3)),
+            Function::Max | Function::Min => (1, None),
+            Function::If => (3, Some(3)),
+        }
+    }
+}
+
+// Binary operator precedence; postfix ! binds tightest, then ^, then prefix functions and unary minus
 #[derive(PartialEq, PartialOrd)]
-pub(super) enum Precedence {
+enum Precedence {
     Lowest,
+    Or,             // ||
+    And,            // &&
+    Comparison,     // == != < <= > >=
     Addition,       // + -
     Multiplication, // * / %
-    Exponentiation, // ^ log
-    Unary,          // ! sqrt sin cos tan ln (highest precedence)
+    Implicit,       // juxtaposition like 2pi or 3(4 + 5)
+    Exponentiation, // ^ log (highest binary precedence)
 }
 
-pub(super) struct Parser<'a> {
-    chars: std::iter::Peekable<std::str::Chars<'a>>,
+// How operators of equal precedence group: 8 - 2 - 1 is (8 - 2) - 1, 2 ^ 3 ^ 2 is 2 ^ (3 ^ 2)
+#[derive(PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+pub(crate) struct Parser<'a> {
+    input: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    functions: HashMap<String, usize>, // user-defined function names and their parameter counts
+    complex: bool,                     // whether i is the imaginary unit rather than a name
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(input: &'a str) -> Self {
+    pub(crate) fn new(input: &'a str, functions: HashMap<String, usize>, complex: bool) -> Self {
         Self {
-            chars: input.chars().peekable(),
+            input,
+            chars: input.char_indices().peekable(),
+            functions,
+            complex,
         }
     }
 
-    pub(crate) fn parse(&mut self) -> Result<Token, Box<dyn std::error::Error>> {
-        let expression = self.parse_expression(Precedence::Lowest)?;
-        self.skip_whitespace();
+    // Parses statements separated by ';' or a newline; empty statements and # comments are
+    // skipped
+    pub(crate) fn parse(&mut self) -> Result<Vec<Token>, ParseError> {
+        let mut statements = Vec::new();
 
-        if self.chars.peek().is_some() {
-            return Err("Unexpected characters at end of input".into());
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => break,
+                Some(';' | '\n') => {
+                    self.advance();
+                    continue;
+                }
+                Some(_) => statements.push(self.parse_statement()?),
+            }
         }
 
-        Ok(expression)
+        Ok(statements)
     }
 
-    fn parse_expression(
-        &mut self,
-        precedence: Precedence,
-    ) -> Result<Token, Box<dyn std::error::Error>> {
-        // Handle top-level expressions starting with binary operators (use last result as left operand)
-        if precedence == Precedence::Lowest {
-            if let Some(token) = self.try_parse_binary_with_last_result()? {
-                return Ok(token);
+    fn parse_statement(&mut self) -> Result<Token, ParseError> {
+        // Handle statements starting with binary operators (use last result as left operand)
+        let expression = if let Some(token) = self.try_parse_assignment()? {
+            token
+        } else if let Some(token) = self.try_parse_binary_with_last_result()? {
+            token
+        } else {
+            self.parse_expression(Precedence::Lowest)?
+        };
+        self.skip_whitespace();
+
+        let start = self.position();
+        match self.peek() {
+            Some(')') => Err(ParseError::UnbalancedParen {
+                span: start..start + 1,
+            }),
+            None | Some(';' | '\n') => Ok(expression),
+            Some(_) => {
+                // Underline up to the end of the statement, excluding a trailing comment
+                let rest = &self.input[start..];
+                let end = rest.find([';', '\n', '#']).unwrap_or(rest.len());
+                Err(ParseError::TrailingInput {
+                    span: start..start + rest[..end].trim_end().len(),
+                })
             }
         }
+    }
 
+    fn parse_expression(&mut self, precedence: Precedence) -> Result<Token, ParseError> {
         let mut left = self.parse_primary()?;
         left = self.parse_postfix_operators(left)?;
         left = self.parse_binary_operators(left, precedence)?;
@@ -95,22 +439,139 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
 
-    fn try_parse_binary_with_last_result(
-        &mut self,
-    ) -> Result<Option<Token>, Box<dyn std::error::Error>> {
+    // Parses `name = expression` and `name(a, b) = expression`;
+    // built-in constant and function names cannot be assigned or defined
+    fn try_parse_assignment(&mut self) -> Result<Option<Token>, ParseError> {
+        let saved_position = self.chars.clone();
+        let start = self.position();
+        let name = self.consume_alphabetic_word();
+        let name_span = start..self.position();
         self.skip_whitespace();
 
-        if let Some(&c) = self.chars.peek() {
-            if let Some(operator) = self.try_parse_single_char_binary_operator(c) {
-                self.chars.next();
-                let right = self.parse_expression(Precedence::Unary)?;
-                return Ok(Some(Token::Binary(BinaryToken {
-                    left: Box::new(Token::LastResult),
-                    operation: operator,
-                    right: Box::new(right),
-                })));
+        // None for a variable assignment, the parameters for a function definition
+        let parameters = match self.peek() {
+            Some('(') => self.try_parse_parameter_list().map(Some),
+            _ => Some(None),
+        };
+        self.skip_whitespace();
+
+        let parameters = match parameters {
+            Some(parameters)
+                if !name.is_empty()
+                    && self.peek() == Some('=')
+                    && !self.peek_word_matches("==") =>
+            {
+                parameters
+            }
+            _ => {
+                self.chars = saved_position;
+                return Ok(None);
+            }
+        };
+
+        if self.is_reserved(&name) {
+            return Err(ParseError::ReservedName {
+                name,
+                span: name_span,
+            });
+        }
+
+        self.advance(); // consume '='
+
+        let Some(parameters) = parameters else {
+            let value = self.parse_expression(Precedence::Lowest)?;
+            return Ok(Some(Token::Assignment(AssignmentToken {
+                name,
+                value: Box::new(value),
+            })));
+        };
+
+        for (i, (parameter, span)) in parameters.iter().enumerate() {
+            if self.is_reserved(parameter) {
+                return Err(ParseError::ReservedName {
+                    name: parameter.clone(),
+                    span: span.clone(),
+                });
+            }
+            if parameters[..i].iter().any(|(other, _)| other == parameter) {
+                return Err(ParseError::DuplicateParameter {
+                    name: parameter.clone(),
+                    span: span.clone(),
+                });
+            }
+        }
+
+        // Registered before parsing the body so the function can call itself
+        self.functions.insert(name.clone(), parameters.len());
+        let body = self.parse_expression(Precedence::Lowest)?;
+        // The parser may have skipped past whitespace and a comment after the body
+        let source = self.input[start..self.position()]
+            .split('#')
+            .next()
+            .unwrap_or_default();
+        let source = source.trim_end().to_string();
+
+        Ok(Some(Token::Definition(DefinitionToken {
+            name,
+            parameters: parameters
+                .into_iter()
+                .map(|(parameter, _)| parameter)
+                .collect(),
+            body: Box::new(body),
+            source,
+        })))
+    }
+
+    // Parses `(a, b, ...)` as a list of parameter names, or returns None if it is not one
+    fn try_parse_parameter_list(&mut self) -> Option<Vec<(String, Span)>> {
+        self.advance(); // consume '('
+        let mut parameters = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.advance();
+            return Some(parameters);
+        }
+
+        loop {
+            self.skip_whitespace();
+            let start = self.position();
+            let parameter = self.consume_alphabetic_word();
+            if parameter.is_empty() {
+                return None;
+            }
+            parameters.push((parameter, start..self.position()));
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.advance(),
+                Some(')') => {
+                    self.advance();
+                    return Some(parameters);
+                }
+                _ => return None,
             }
         }
+    }
+
+    fn try_parse_binary_with_last_result(&mut self) -> Result<Option<Token>, ParseError> {
+        self.skip_whitespace();
+
+        if let Some(c) = self.peek()
+            && let Some(operator) = self.try_parse_single_char_binary_operator(c)
+        {
+            let (op_precedence, _) = self.get_precedence(c);
+            self.advance();
+            let right = self.parse_expression(op_precedence)?;
+            let token = Token::Binary(BinaryToken {
+                left: Box::new(Token::LastResult),
+                operation: operator,
+                right: Box::new(right),
+            });
+            return Ok(Some(
+                self.parse_binary_operators(token, Precedence::Lowest)?,
+            ));
+        }
 
         Ok(None)
     }
@@ -119,20 +580,23 @@ impl<'a> Parser<'a> {
         &mut self,
         mut left: Token,
         precedence: Precedence,
-    ) -> Result<Token, Box<dyn std::error::Error>> {
+    ) -> Result<Token, ParseError> {
         loop {
             self.skip_whitespace();
 
-            let next_char = match self.chars.peek() {
-                Some(&c) => c,
+            let next_char = match self.peek() {
+                Some(c) => c,
                 None => break,
             };
 
-            let op_precedence = self.get_precedence(next_char);
+            let (op_precedence, associativity) = self.get_precedence(next_char);
 
-            if op_precedence > precedence
-                && let Some(operator) = self.parse_binary_operator()
-            {
+            // A right-associative operator also binds at its own level, so the right
+            // operand of 2 ^ 3 keeps going and picks up ^ 2 in 2 ^ 3 ^ 2
+            let binds = op_precedence > precedence
+                || (op_precedence == precedence && associativity == Associativity::Right);
+
+            if binds && let Some(operator) = self.parse_binary_operator() {
                 let right = self.parse_expression(op_precedence)?;
                 left = Token::Binary(BinaryToken {
                     left: Box::new(left),
@@ -147,18 +611,38 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
 
-    fn parse_postfix_operators(
-        &mut self,
-        mut expr: Token,
-    ) -> Result<Token, Box<dyn std::error::Error>> {
+    fn parse_postfix_operators(&mut self, mut expr: Token) -> Result<Token, ParseError> {
         loop {
             self.skip_whitespace();
-            if self.chars.peek() == Some(&'!') {
-                self.chars.next();
+            // ! after an operand is factorial, unless it starts != (write 3! == 6 for the other reading)
+            // and !! is double factorial, so the factorial of a factorial needs (3!)! or 3! !
+            if self.peek_word_matches("!!") && !self.peek_word_matches("!!=") {
+                self.advance();
+                self.advance();
+                expr = Token::Unary(UnaryToken {
+                    operation: UnaryOperator::DoubleFactorial,
+                    operand: Box::new(expr),
+                });
+            } else if self.peek() == Some('!') && !self.peek_word_matches("!=") {
+                self.advance();
                 expr = Token::Unary(UnaryToken {
                     operation: UnaryOperator::Factorial,
                     operand: Box::new(expr),
                 });
+            } else if self.peek() == Some('°') {
+                self.advance();
+                expr = Token::Unary(UnaryToken {
+                    operation: UnaryOperator::Angle(AngleUnit::Degrees),
+                    operand: Box::new(expr),
+                });
+            } else if self.peek() == Some('[') {
+                expr = self.parse_index(expr)?;
+            } else if let Some(exponent) = self.parse_superscript() {
+                expr = Token::Binary(BinaryToken {
+                    left: Box::new(expr),
+                    operation: BinaryOperator::Power,
+                    right: Box::new(Token::Value(BigRational::from_integer(exponent))),
+                });
             } else {
                 break;
             }
@@ -166,83 +650,461 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
-    fn parse_primary(&mut self) -> Result<Token, Box<dyn std::error::Error>> {
+    fn parse_primary(&mut self) -> Result<Token, ParseError> {
         self.skip_whitespace();
 
-        match self.chars.peek() {
-            Some(&c) if c.is_ascii_digit() || c == '.' => self.parse_number(),
-            Some(&c) if c.is_alphabetic() => self.parse_unary_operator(),
-            Some(&'-') => self.parse_unary_minus(),
-            Some(&'(') => self.parse_parenthesized_expression(),
-            Some(&c) => Err(format!("Unexpected character: '{}'", c).into()),
-            None => Err("Unexpected end of input".into()),
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => self.parse_number(),
+            Some(c) if c.is_alphabetic() || c == '√' => self.parse_unary_operator(),
+            Some(c) if let Some(fraction) = vulgar_fraction(c) => {
+                self.advance();
+                Ok(Token::Value(fraction))
+            }
+            Some('-' | '−') => self.parse_unary_minus(),
+            Some('!') => self.parse_logical_not(),
+            Some('(') => self.parse_parenthesized_expression(),
+            Some('[') => self.parse_list(),
+            Some(')') => Err(ParseError::UnbalancedParen {
+                span: self.char_span(),
+            }),
+            Some(']') => Err(ParseError::UnbalancedBracket {
+                span: self.char_span(),
+            }),
+            // A newline ends the statement
+            None | Some('\n') => Err(ParseError::UnexpectedEnd {
+                span: self.char_span(),
+            }),
+            Some(found) => Err(ParseError::UnexpectedChar {
+                found,
+                span: self.char_span(),
+            }),
         }
     }
 
-    fn parse_unary_minus(&mut self) -> Result<Token, Box<dyn std::error::Error>> {
-        self.chars.next(); // consume '-'
-        let operand = self.parse_primary()?;
+    fn parse_unary_minus(&mut self) -> Result<Token, ParseError> {
+        self.advance(); // consume '-'
+        // Negation binds looser than ^ and !, so -2 ^ 2 is -(2 ^ 2)
+        let operand = self.parse_expression(Precedence::Implicit)?;
         Ok(Token::Unary(UnaryToken {
             operation: UnaryOperator::Negate,
             operand: Box::new(operand),
         }))
     }
 
-    fn parse_parenthesized_expression(&mut self) -> Result<Token, Box<dyn std::error::Error>> {
-        self.chars.next(); // consume '('
+    fn parse_logical_not(&mut self) -> Result<Token, ParseError> {
+        self.advance(); // consume '!'
+        let operand = self.parse_expression(Precedence::Implicit)?;
+        Ok(Token::Unary(UnaryToken {
+            operation: UnaryOperator::Not,
+            operand: Box::new(operand),
+        }))
+    }
+
+    fn parse_parenthesized_expression(&mut self) -> Result<Token, ParseError> {
+        let open = self.char_span();
+        self.advance(); // consume '('
         let expr = self.parse_expression(Precedence::Lowest)?;
         self.skip_whitespace();
 
-        if self.chars.next() != Some(')') {
-            return Err("Expected ')'".into());
+        match self.peek() {
+            Some(')') => {
+                self.advance();
+                Ok(expr)
+            }
+            None | Some('\n') => Err(ParseError::UnbalancedParen { span: open }),
+            Some(found) => Err(ParseError::UnexpectedChar {
+                found,
+                span: self.char_span(),
+            }),
         }
+    }
 
-        Ok(expr)
+    fn parse_list(&mut self) -> Result<Token, ParseError> {
+        let open = self.char_span();
+        self.advance(); // consume '['
+        let mut elements = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.advance();
+            return Ok(Token::List(elements));
+        }
+        loop {
+            elements.push(self.parse_expression(Precedence::Lowest)?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.advance(),
+                Some(']') => {
+                    self.advance();
+                    return Ok(Token::List(elements));
+                }
+                None | Some('\n') => return Err(ParseError::UnbalancedBracket { span: open }),
+                Some(found) => {
+                    return Err(ParseError::UnexpectedChar {
+                        found,
+                        span: self.char_span(),
+                    });
+                }
+            }
+        }
+    }
+
+    // [ after an operand picks an element of it, as in xs[1] or [3, 1, 4][-1]
+    fn parse_index(&mut self, list: Token) -> Result<Token, ParseError> {
+        let open = self.char_span();
+        self.advance(); // consume '['
+        let index = self.parse_expression(Precedence::Lowest)?;
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(']') => {
+                self.advance();
+                Ok(Token::Index(IndexToken {
+                    list: Box::new(list),
+                    index: Box::new(index),
+                }))
+            }
+            None | Some('\n') => Err(ParseError::UnbalancedBracket { span: open }),
+            Some(found) => Err(ParseError::UnexpectedChar {
+                found,
+                span: self.char_span(),
+            }),
+        }
     }
 
-    fn parse_number(&mut self) -> Result<Token, Box<dyn std::error::Error>> {
-        let mut num_str = String::new();
+    fn parse_number(&mut self) -> Result<Token, ParseError> {
+        if let Some(radix) = self.radix_prefix() {
+            return self.parse_radix_number(radix);
+        }
+
+        let start = self.position();
 
-        while let Some(&c) = self.chars.peek() {
-            if c.is_ascii_digit() || c == '.' {
-                num_str.push(c);
-                self.chars.next();
+        // Underscores may separate digits, e.g. 1_000_000
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || c == '.' || (c == '_' && self.position() > start) {
+                self.advance();
             } else {
                 break;
             }
         }
 
-        let value = num_str.parse::<f64>()?;
-        Ok(Token::Value(value))
+        // Exponent part, e.g. 1.5e-3; a bare 'e' is left for the constant
+        if self.exponent_follows() {
+            self.advance(); // consume 'e' or 'E'
+            if let Some('+' | '-') = self.peek() {
+                self.advance();
+            }
+            while let Some(c) = self.peek() {
+                if c.is_ascii_digit() {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        let span = start..self.position();
+        let text = &self.input[span.clone()];
+        let value = match decimal_value(&text.replace('_', "")) {
+            Some(value) if !text.ends_with('_') => value,
+            _ => {
+                return Err(ParseError::BadNumber {
+                    text: text.to_string(),
+                    span,
+                });
+            }
+        };
+
+        // Mixed numbers like 1½
+        if let Some(fraction) = self.peek().and_then(vulgar_fraction) {
+            self.advance();
+            return Ok(Token::Value(value + fraction));
+        }
+
+        Ok(self.parse_angle_suffix(Token::Value(value)))
+    }
+
+    // Angle units written right after a number, like 90deg or 1.2rad
+    fn parse_angle_suffix(&mut self, number: Token) -> Token {
+        for (suffix, unit) in ANGLE_SUFFIXES {
+            if self.peek_keyword(suffix) {
+                suffix.chars().for_each(|_| self.advance());
+                return Token::Unary(UnaryToken {
+                    operation: UnaryOperator::Angle(unit),
+                    operand: Box::new(number),
+                });
+            }
+        }
+        number
+    }
+
+    // Parses 0x, 0b and 0o integer literals, reporting the first digit invalid for the radix
+    fn parse_radix_number(&mut self, radix: u32) -> Result<Token, ParseError> {
+        let start = self.position();
+        self.advance(); // consume '0'
+        self.advance(); // consume the radix letter
+
+        let mut value = BigInt::zero();
+        let mut digits = 0;
+
+        while let Some(c) = self.peek() {
+            if c == '_' && digits > 0 {
+                self.advance();
+                continue;
+            }
+            if !c.is_ascii_alphanumeric() {
+                break;
+            }
+
+            let digit = match c.to_digit(radix) {
+                Some(digit) => digit,
+                None => {
+                    return Err(ParseError::InvalidDigit {
+                        digit: c,
+                        radix,
+                        span: self.char_span(),
+                    });
+                }
+            };
+            self.advance();
+            digits += 1;
+            value = value * radix + digit;
+        }
+
+        let span = start..self.position();
+        let text = &self.input[span.clone()];
+        if digits == 0 || text.ends_with('_') {
+            return Err(ParseError::BadNumber {
+                text: text.to_string(),
+                span,
+            });
+        }
+
+        Ok(Token::Value(BigRational::from_integer(value)))
     }
 
-    fn parse_unary_operator(&mut self) -> Result<Token, Box<dyn std::error::Error>> {
-        let op_str = self.consume_alphabetic_word();
+    // Detects a radix prefix (0x, 0b, 0o) without consuming anything
+    fn radix_prefix(&self) -> Option<u32> {
+        let mut peekable = self.chars.clone();
+        if !matches!(peekable.next(), Some((_, '0'))) {
+            return None;
+        }
+        match peekable.next() {
+            Some((_, 'x' | 'X')) => Some(16),
+            Some((_, 'b' | 'B')) => Some(2),
+            Some((_, 'o' | 'O')) => Some(8),
+            _ => None,
+        }
+    }
+
+    // Checks for 'e'/'E' followed by digits, optionally signed, without consuming anything
+    fn exponent_follows(&self) -> bool {
+        let mut peekable = self.chars.clone();
+        if !matches!(peekable.next(), Some((_, 'e' | 'E'))) {
+            return false;
+        }
+        match peekable.next() {
+            Some((_, '+' | '-')) => matches!(peekable.next(), Some((_, c)) if c.is_ascii_digit()),
+            Some((_, c)) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    fn parse_unary_operator(&mut self) -> Result<Token, ParseError> {
+        let start = self.position();
+        let op_str = if self.peek() == Some('√') {
+            self.advance();
+            "sqrt".to_string()
+        } else {
+            self.consume_alphabetic_word()
+        };
+        let name_span = start..self.position();
 
         // Check if it's a constant first
         if let Some(constant) = self.parse_constant(&op_str) {
             return Ok(Token::Constant(constant));
         }
 
-        let operation = self.parse_unary_operator_type(&op_str)?;
-
         self.skip_whitespace();
 
-        let operand = if self.chars.peek().is_none() {
+        // Any function name directly followed by '(' is a call with an argument list. Other
+        // names are variables, which multiply the parentheses like any operand: x(2) = x * 2
+        if self.peek() == Some('(') {
+            if let Some(&arity) = self.functions.get(&op_str) {
+                let arguments = self.parse_arguments(&op_str, (arity, Some(arity)), start)?;
+                return Ok(Token::UserCall(UserCallToken {
+                    name: op_str,
+                    arguments,
+                }));
+            }
+            if let Ok(function) = self.parse_function_type(&op_str, name_span.clone()) {
+                return self.parse_function_call(function, &op_str, start);
+            }
+        }
+
+        // Any other name that is not a function refers to a variable
+        let operation = match self.parse_unary_operator_type(&op_str, name_span) {
+            Ok(operation) => operation,
+            Err(_) => return Ok(Token::Variable(op_str)),
+        };
+
+        // Without parentheses the function applies to the implicit product after it: sin 2pi = sin(2pi)
+        let operand = if matches!(self.peek(), None | Some(';' | '\n')) {
             Box::new(Token::LastResult)
         } else {
-            Box::new(self.parse_primary()?)
+            Box::new(self.parse_expression(Precedence::Multiplication)?)
         };
 
         Ok(Token::Unary(UnaryToken { operation, operand }))
     }
 
+    fn parse_function_call(
+        &mut self,
+        function: Function,
+        name: &str,
+        start: usize,
+    ) -> Result<Token, ParseError> {
+        // sum(k, 1, 100, k^2) binds k, while sum(xs) adds up a list
+        if matches!(function, Function::Sum | Function::Product)
+            && let Some((variable, span)) = self.peek_bound_variable()
+        {
+            return self.parse_series(function, variable, span, name, start);
+        }
+        let mut arguments = match self.parse_arguments(name, function.arity(), start) {
+            // Four arguments are a sum or product with something other than a name to bind
+            Err(ParseError::WrongArgumentCount { found: 4, span, .. })
+                if matches!(function, Function::Sum | Function::Product) =>
+            {
+                return Err(ParseError::IndexNotName {
+                    name: name.to_string(),
+                    span,
+                });
+            }
+            arguments => arguments?,
+        };
+
+        let token = match function {
+            Function::Unary(operation) => Token::Unary(UnaryToken {
+                operation,
+                operand: Box::new(arguments.remove(0)),
+            }),
+            function => Token::Call(CallToken {
+                function,
+                arguments,
+            }),
+        };
+
+        Ok(token)
+    }
+
+    // The name in `(name,` at the start of an argument list, without consuming anything
+    fn peek_bound_variable(&mut self) -> Option<(String, Span)> {
+        let saved_position = self.chars.clone();
+        self.advance(); // skip '('
+        self.skip_whitespace();
+        let start = self.position();
+        let variable = self.consume_alphabetic_word();
+        let span = start..self.position();
+        self.skip_whitespace();
+        let found = !variable.is_empty() && self.peek() == Some(',');
+        self.chars = saved_position;
+        found.then_some((variable, span))
+    }
+
+    // Parses the arguments of sum(k, from, to, body) or prod(k, from, to, body)
+    fn parse_series(
+        &mut self,
+        function: Function,
+        variable: String,
+        span: Span,
+        name: &str,
+        start: usize,
+    ) -> Result<Token, ParseError> {
+        if self.is_reserved(&variable) {
+            return Err(ParseError::ReservedName {
+                name: variable,
+                span,
+            });
+        }
+        let mut arguments = self.parse_arguments(name, (4, Some(4)), start)?.into_iter();
+        arguments.next(); // the variable, which was already read
+        let mut next = || Box::new(arguments.next().unwrap());
+        let (from, to, body) = (next(), next(), next());
+        let operation = if matches!(function, Function::Sum) {
+            BinaryOperator::Add
+        } else {
+            BinaryOperator::Multiply
+        };
+        Ok(Token::Series(SeriesToken {
+            operation,
+            variable,
+            from,
+            to,
+            body,
+        }))
+    }
+
+    // Parses a parenthesized argument list and checks it against the (min, max) arity
+    fn parse_arguments(
+        &mut self,
+        name: &str,
+        (min, max): (usize, Option<usize>),
+        start: usize,
+    ) -> Result<Vec<Token>, ParseError> {
+        let open = self.char_span();
+        self.advance(); // consume '('
+        let mut arguments = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.advance();
+        } else {
+            loop {
+                arguments.push(self.parse_expression(Precedence::Lowest)?);
+                self.skip_whitespace();
+
+                match self.peek() {
+                    Some(',') => self.advance(),
+                    Some(')') => {
+                        self.advance();
+                        break;
+                    }
+                    None | Some('\n') => return Err(ParseError::UnbalancedParen { span: open }),
+                    Some(found) => {
+                        return Err(ParseError::UnexpectedChar {
+                            found,
+                            span: self.char_span(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if arguments.len() < min || max.is_some_and(|max| arguments.len() > max) {
+            let expected = match max {
+                Some(max) if max == min => format!("{}", min),
+                Some(max) => format!("{} to {}", min, max),
+                None => format!("at least {}", min),
+            };
+            return Err(ParseError::WrongArgumentCount {
+                name: name.to_string(),
+                expected,
+                found: arguments.len(),
+                span: start..self.position(),
+            });
+        }
+
+        Ok(arguments)
+    }
+
     fn consume_alphabetic_word(&mut self) -> String {
         let mut word = String::new();
-        while let Some(&c) = self.chars.peek() {
-            if c.is_alphabetic() {
+        while let Some(c) = self.peek() {
+            // Digits are allowed after the first letter so names like atan2 work
+            if c.is_alphabetic() || (!word.is_empty() && c.is_ascii_digit()) {
                 word.push(c);
-                self.chars.next();
+                self.advance();
             } else {
                 break;
             }
@@ -252,73 +1114,164 @@ impl<'a> Parser<'a> {
 
     fn parse_constant(&self, op_str: &str) -> Option<Constant> {
         match op_str {
-            "pi" => Some(Constant::Pi),
-            "e" => Some(Constant::E),
+            "pi" | "π" => Some(Constant::Pi),
+            "e" | "ℯ" => Some(Constant::E),
+            "i" if self.complex => Some(Constant::I),
             _ => None,
         }
     }
 
+    // Names of constants and built-in functions, which variables cannot shadow
+    fn is_reserved(&self, name: &str) -> bool {
+        self.parse_constant(name).is_some()
+            || self.parse_function_type(name, 0..0).is_ok()
+            || ANGLE_SUFFIXES.iter().any(|(suffix, _)| *suffix == name)
+    }
+
     fn parse_unary_operator_type(
         &self,
         op_str: &str,
-    ) -> Result<UnaryOperator, Box<dyn std::error::Error>> {
+        span: Span,
+    ) -> Result<UnaryOperator, ParseError> {
         match op_str {
             "sqrt" => Ok(UnaryOperator::SquareRoot),
             "sin" => Ok(UnaryOperator::Sin),
             "cos" => Ok(UnaryOperator::Cos),
             "tan" => Ok(UnaryOperator::Tan),
+            "sec" => Ok(UnaryOperator::Sec),
+            "csc" => Ok(UnaryOperator::Csc),
+            "cot" => Ok(UnaryOperator::Cot),
+            "asin" => Ok(UnaryOperator::Asin),
+            "acos" => Ok(UnaryOperator::Acos),
+            "atan" => Ok(UnaryOperator::Atan),
+            "sinh" => Ok(UnaryOperator::Sinh),
+            "cosh" => Ok(UnaryOperator::Cosh),
+            "tanh" => Ok(UnaryOperator::Tanh),
+            "asinh" => Ok(UnaryOperator::Asinh),
+            "acosh" => Ok(UnaryOperator::Acosh),
+            "atanh" => Ok(UnaryOperator::Atanh),
             "ln" => Ok(UnaryOperator::Ln),
             "floor" => Ok(UnaryOperator::Floor),
             "ceil" => Ok(UnaryOperator::Ceil),
             "abs" => Ok(UnaryOperator::Abs),
             "round" => Ok(UnaryOperator::Round),
-            _ => Err(format!("Unknown unary operator: {}", op_str).into()),
+            "gamma" => Ok(UnaryOperator::Gamma),
+            "lgamma" => Ok(UnaryOperator::LogGamma),
+            "digamma" => Ok(UnaryOperator::Digamma),
+            "re" => Ok(UnaryOperator::Real),
+            "im" => Ok(UnaryOperator::Imaginary),
+            "arg" => Ok(UnaryOperator::Argument),
+            "conj" => Ok(UnaryOperator::Conjugate),
+            _ => Err(ParseError::UnknownFunction {
+                name: op_str.to_string(),
+                span,
+            }),
+        }
+    }
+
+    fn parse_function_type(&self, name: &str, span: Span) -> Result<Function, ParseError> {
+        match name {
+            "log" => Ok(Function::Log),
+            "atan2" => Ok(Function::Atan2),
+            "hypot" => Ok(Function::Hypot),
+            "beta" => Ok(Function::Beta),
+            "gcd" => Ok(Function::Gcd),
+            "lcm" => Ok(Function::Lcm),
+            "isprime" => Ok(Function::IsPrime),
+            "factor" => Ok(Function::Factor),
+            "nextprime" => Ok(Function::NextPrime),
+            "totient" => Ok(Function::Totient),
+            "modpow" => Ok(Function::ModPow),
+            "modinv" => Ok(Function::ModInverse),
+            "ncr" => Ok(Function::Choose),
+            "npr" => Ok(Function::Permutations),
+            "multinomial" => Ok(Function::Multinomial),
+            "subfactorial" => Ok(Function::Subfactorial),
+            "length" => Ok(Function::Length),
+            "concat" => Ok(Function::Concat),
+            "sort" => Ok(Function::Sort),
+            "sum" => Ok(Function::Sum),
+            "prod" => Ok(Function::Product),
+            "mean" => Ok(Function::Mean),
+            "median" => Ok(Function::Median),
+            "mode" => Ok(Function::Mode),
+            "var" => Ok(Function::Variance),
+            "varp" => Ok(Function::PopulationVariance),
+            "stdev" => Ok(Function::Deviation),
+            "stdevp" => Ok(Function::PopulationDeviation),
+            "range" => Ok(Function::Range),
+            "percentile" => Ok(Function::Percentile),
+            "quartiles" => Ok(Function::Quartiles),
+            "zscore" => Ok(Function::ZScore),
+            "det" => Ok(Function::Determinant),
+            "inv" => Ok(Function::Inverse),
+            "transpose" => Ok(Function::Transpose),
+            "rank" => Ok(Function::Rank),
+            "trace" => Ok(Function::Trace),
+            "identity" => Ok(Function::Identity),
+            "linsolve" => Ok(Function::LinearSolve),
+            "max" => Ok(Function::Max),
+            "min" => Ok(Function::Min),
+            "if" => Ok(Function::If),
+            _ => Ok(Function::Unary(self.parse_unary_operator_type(name, span)?)),
         }
     }
 
     fn parse_binary_operator(&mut self) -> Option<BinaryOperator> {
-        match self.chars.peek() {
-            Some(&'+') => {
-                self.chars.next();
-                Some(BinaryOperator::Add)
+        match self.peek() {
+            Some(_) if let Some((operator, length)) = self.peek_logical_operator() => {
+                for _ in 0..length {
+                    self.advance();
+                }
+                Some(operator)
             }
-            Some(&'-') => {
-                self.chars.next();
-                Some(BinaryOperator::Subtract)
+            Some('l') if self.peek_keyword("log") => self.try_parse_log_operator(),
+            // Implicit multiplication consumes nothing, the operand is parsed next
+            Some(c) if self.starts_implicit_operand(c) => Some(BinaryOperator::Multiply),
+            Some(c) => {
+                let operator = self.try_parse_single_char_binary_operator(c)?;
+                self.advance();
+                Some(operator)
             }
-            Some(&'*') => {
-                self.chars.next();
-                Some(BinaryOperator::Multiply)
-            }
-            Some(&'/') => {
-                self.chars.next();
-                Some(BinaryOperator::Divide)
-            }
-            Some(&'^') => {
-                self.chars.next();
-                Some(BinaryOperator::Power)
-            }
-            Some(&'%') => {
-                self.chars.next();
-                Some(BinaryOperator::Modulo)
-            }
-            Some(&'l') => self.try_parse_log_operator(),
-            _ => None,
+            None => None,
         }
     }
 
     fn try_parse_single_char_binary_operator(&self, c: char) -> Option<BinaryOperator> {
         match c {
             '+' => Some(BinaryOperator::Add),
-            '-' => Some(BinaryOperator::Subtract),
-            '*' => Some(BinaryOperator::Multiply),
-            '/' => Some(BinaryOperator::Divide),
+            '-' | '−' => Some(BinaryOperator::Subtract),
+            '*' | '×' | '·' | '⋅' | '∗' => Some(BinaryOperator::Multiply),
+            '/' | '÷' | '∕' => Some(BinaryOperator::Divide),
             '^' => Some(BinaryOperator::Power),
             '%' => Some(BinaryOperator::Modulo),
+            '@' => Some(BinaryOperator::MatrixMultiply),
             _ => None,
         }
     }
 
+    // Recognises comparison and boolean operators, returning the operator and its length in chars
+    fn peek_logical_operator(&self) -> Option<(BinaryOperator, usize)> {
+        let operators = [
+            ("==", BinaryOperator::Equal),
+            ("!=", BinaryOperator::NotEqual),
+            ("<=", BinaryOperator::LessEqual),
+            (">=", BinaryOperator::GreaterEqual),
+            ("&&", BinaryOperator::And),
+            ("||", BinaryOperator::Or),
+            ("<", BinaryOperator::Less),
+            (">", BinaryOperator::Greater),
+            ("≠", BinaryOperator::NotEqual),
+            ("≤", BinaryOperator::LessEqual),
+            ("≥", BinaryOperator::GreaterEqual),
+        ];
+
+        operators
+            .into_iter()
+            .find(|(symbol, _)| self.peek_word_matches(symbol))
+            .map(|(symbol, operator)| (operator, symbol.chars().count()))
+    }
+
     fn try_parse_log_operator(&mut self) -> Option<BinaryOperator> {
         let saved_position = self.chars.clone();
         let word = self.consume_alphabetic_word();
@@ -331,34 +1284,172 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn get_precedence(&self, c: char) -> Precedence {
+    fn get_precedence(&self, c: char) -> (Precedence, Associativity) {
+        if let Some((operator, _)) = self.peek_logical_operator() {
+            return match operator {
+                BinaryOperator::Or => (Precedence::Or, Associativity::Left),
+                BinaryOperator::And => (Precedence::And, Associativity::Left),
+                _ => (Precedence::Comparison, Associativity::Left),
+            };
+        }
+
         match c {
-            '+' | '-' => Precedence::Addition,
-            '*' | '/' | '%' => Precedence::Multiplication,
-            '^' => Precedence::Exponentiation,
-            'l' if self.peek_word_matches("log") => Precedence::Exponentiation,
-            _ => Precedence::Lowest,
+            '+' | '-' | '−' => (Precedence::Addition, Associativity::Left),
+            '*' | '×' | '·' | '⋅' | '∗' | '/' | '÷' | '∕' | '%' | '@' => {
+                (Precedence::Multiplication, Associativity::Left)
+            }
+            '^' => (Precedence::Exponentiation, Associativity::Right),
+            'l' if self.peek_keyword("log") => (Precedence::Exponentiation, Associativity::Left),
+            c if self.starts_implicit_operand(c) => (Precedence::Implicit, Associativity::Left),
+            _ => (Precedence::Lowest, Associativity::Left),
         }
     }
 
+    // A name or '(' right after an operand multiplies it; a bare number does not (2 3 is an error)
+    fn starts_implicit_operand(&self, c: char) -> bool {
+        c.is_alphabetic() || c == '(' || c == '√' || vulgar_fraction(c).is_some()
+    }
+
+    // Parses a run of superscript digits like ² or ⁻¹ into an exponent
+    fn parse_superscript(&mut self) -> Option<BigInt> {
+        let mut peekable = self.chars.clone();
+        let negative = matches!(peekable.peek(), Some((_, '⁻')));
+        if negative {
+            peekable.next();
+        }
+        peekable.peek().and_then(|&(_, c)| superscript_digit(c))?;
+
+        if negative {
+            self.advance();
+        }
+        let mut exponent = BigInt::zero();
+        while let Some(digit) = self.peek().and_then(superscript_digit) {
+            self.advance();
+            exponent = exponent * 10 + digit;
+        }
+
+        Some(if negative { -exponent } else { exponent })
+    }
+
     fn peek_word_matches(&self, word: &str) -> bool {
         let mut peekable = self.chars.clone();
         for expected_char in word.chars() {
             match peekable.next() {
-                Some(c) if c == expected_char => continue,
+                Some((_, c)) if c == expected_char => continue,
                 _ => return false,
             }
         }
         true
     }
 
+    // Like peek_word_matches, but the whole upcoming name must match (log, not logx)
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        let mut peekable = self.chars.clone();
+        for expected_char in keyword.chars() {
+            match peekable.next() {
+                Some((_, c)) if c == expected_char => continue,
+                _ => return false,
+            }
+        }
+        !matches!(peekable.next(), Some((_, c)) if c.is_alphanumeric())
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn advance(&mut self) {
+        self.chars.next();
+    }
+
+    // Byte offset of the next unconsumed character
+    fn position(&mut self) -> usize {
+        match self.chars.peek() {
+            Some(&(i, _)) => i,
+            None => self.input.len(),
+        }
+    }
+
+    // Span covering the next character, or the end of input
+    fn char_span(&mut self) -> Span {
+        let start = self.position();
+        start..start + self.peek().map_or(1, char::len_utf8)
+    }
+
+    // Skips whitespace and # comments, which run to the end of the line. Newlines end a
+    // statement and are left for the caller.
     fn skip_whitespace(&mut self) {
-        while let Some(&c) = self.chars.peek() {
-            if c.is_whitespace() {
-                self.chars.next();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() && c != '\n' {
+                self.advance();
+            } else if c == '#' {
+                while !matches!(self.peek(), None | Some('\n')) {
+                    self.advance();
+                }
             } else {
                 break;
             }
         }
     }
 }
+
+fn superscript_digit(c: char) -> Option<u32> {
+    match c {
+        '⁰' => Some(0),
+        '¹' => Some(1),
+        '²' => Some(2),
+        '³' => Some(3),
+        '⁴'..='⁹' => Some(c as u32 - '⁴' as u32 + 4),
+        _ => None,
+    }
+}
+
+fn vulgar_fraction(c: char) -> Option<BigRational> {
+    let (numerator, denominator) = match c {
+        '½' => (1, 2),
+        '⅓' => (1, 3),
+        '⅔' => (2, 3),
+        '¼' => (1, 4),
+        '¾' => (3, 4),
+        '⅕' => (1, 5),
+        '⅖' => (2, 5),
+        '⅗' => (3, 5),
+        '⅘' => (4, 5),
+        '⅙' => (1, 6),
+        '⅚' => (5, 6),
+        '⅐' => (1, 7),
+        '⅛' => (1, 8),
+        '⅜' => (3, 8),
+        '⅝' => (5, 8),
+        '⅞' => (7, 8),
+        '⅑' => (1, 9),
+        '⅒' => (1, 10),
+        _ => return None,
+    };
+    Some(BigRational::new(numerator.into(), denominator.into()))
+}
+
+// Exact value of a decimal literal like 12.5 or 1.5e-3, without digit separators
+fn decimal_value(text: &str) -> Option<BigRational> {
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().ok()?),
+        None => (text, 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+        return None;
+    }
+
+    // 12.5e-3 is 125 * 10^(-3 - 1)
+    let digits: BigInt = format!("{whole}{fraction}").parse().ok()?;
+    let exponent = exponent - fraction.len() as i64;
+    if exponent.unsigned_abs() > MAX_LITERAL_EXPONENT {
+        return None;
+    }
+    let scale = BigInt::from(10).pow(exponent.unsigned_abs());
+
+    Some(match exponent {
+        0.. => BigRational::from_integer(digits * scale),
+        _ => BigRational::new(digits, scale),
+    })
+}
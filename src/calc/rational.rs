@@ -0,0 +1,81 @@
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::calc::integer::{self, MAX_BITS};
+use crate::calc::parser::{BinaryOperator, UnaryOperator};
+
+// Exact versions of the operators for fractions. None means the result is not a fraction
+// (or is a domain error), and the caller falls back to floating point.
+pub(super) fn unary(operation: &UnaryOperator, operand: &BigRational) -> Option<BigRational> {
+    match operation {
+        UnaryOperator::Negate => Some(-operand),
+        UnaryOperator::Abs => Some(operand.abs()),
+        UnaryOperator::Floor => Some(operand.floor()),
+        UnaryOperator::Ceil => Some(operand.ceil()),
+        UnaryOperator::Round => Some(operand.round()), // Halves round away from zero, like f64::round
+        UnaryOperator::Not => Some(truth(operand.is_zero())),
+        UnaryOperator::Real | UnaryOperator::Conjugate => Some(operand.clone()),
+        UnaryOperator::Imaginary => Some(BigRational::zero()),
+        UnaryOperator::Argument if !operand.is_negative() => Some(BigRational::zero()),
+        UnaryOperator::SquareRoot => square_root(operand),
+        UnaryOperator::Factorial if operand.is_integer() => {
+            integer::factorial(operand.numer()).map(BigRational::from_integer)
+        }
+        UnaryOperator::Gamma if operand.is_integer() => {
+            integer::factorial(&(operand.numer() - 1)).map(BigRational::from_integer)
+        }
+        _ => None,
+    }
+}
+
+pub(super) fn binary(
+    operation: &BinaryOperator,
+    left: &BigRational,
+    right: &BigRational,
+) -> Option<BigRational> {
+    match operation {
+        BinaryOperator::Add => Some(left + right),
+        BinaryOperator::Subtract => Some(left - right),
+        BinaryOperator::Multiply => Some(left * right),
+        BinaryOperator::Divide | BinaryOperator::Modulo if right.is_zero() => None,
+        BinaryOperator::Divide => Some(left / right),
+        BinaryOperator::Modulo => Some(left % right), // Sign follows the dividend, like f64 %
+        BinaryOperator::Power => power(left, right),
+        BinaryOperator::Log => None,
+        BinaryOperator::Equal => Some(truth(left == right)),
+        BinaryOperator::NotEqual => Some(truth(left != right)),
+        BinaryOperator::Less => Some(truth(left < right)),
+        BinaryOperator::LessEqual => Some(truth(left <= right)),
+        BinaryOperator::Greater => Some(truth(left > right)),
+        BinaryOperator::GreaterEqual => Some(truth(left >= right)),
+        BinaryOperator::And | BinaryOperator::Or => Some(truth(!right.is_zero())),
+        BinaryOperator::MatrixMultiply => unreachable!(),
+    }
+}
+
+// Integer powers, as long as the result stays a reasonable size
+fn power(base: &BigRational, exponent: &BigRational) -> Option<BigRational> {
+    if !exponent.is_integer() || (base.is_zero() && exponent.is_negative()) {
+        return None;
+    }
+    let exponent = exponent.to_integer().to_i32()?;
+    let bits = base.numer().bits() + base.denom().bits();
+    if bits.saturating_mul(exponent.unsigned_abs() as u64) > MAX_BITS {
+        return None;
+    }
+    Some(num_traits::Pow::pow(base, exponent))
+}
+
+// Square roots of fractions like 4/9 whose numerator and denominator are perfect squares
+fn square_root(operand: &BigRational) -> Option<BigRational> {
+    if operand.is_negative() {
+        return None;
+    }
+    let numerator = integer::square_root(operand.numer())?;
+    let denominator = integer::square_root(operand.denom())?;
+    Some(BigRational::new(numerator, denominator))
+}
+
+pub(super) fn truth(condition: bool) -> BigRational {
+    BigRational::from_integer(integer::truth(condition))
+}
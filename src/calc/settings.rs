@@ -0,0 +1,55 @@
+/// Options that change how a [`Calculator`](crate::Calculator) evaluates or shows results.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Show the result of every `;`-separated statement instead of only the last.
+    pub show_all: bool,
+    /// How numbers are represented while evaluating.
+    pub mode: Mode,
+    /// How exact fractions are shown.
+    pub format: Format,
+    /// Allows complex results like `sqrt(-4)`, shown in this form. `None` keeps every
+    /// result real, so such expressions are errors.
+    pub complex: Option<ComplexFormat>,
+    /// Unit of the angles taken by trig functions like `sin` and returned by their inverses
+    /// like `asin`.
+    pub angle: AngleUnit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Mode {
+    /// Double precision floating point.
+    #[default]
+    Float,
+    /// Exact fractions for `+ - * /` and integer powers, floating point for everything else.
+    Rational,
+    /// Decimal floating point rounded to the given number of significant digits.
+    Decimal { digits: usize },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Format {
+    /// Fractions are shown as `3/10`.
+    #[default]
+    Fraction,
+    /// Fractions are shown as decimals like `0.3`.
+    Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComplexFormat {
+    /// Complex numbers are shown as `3 + 4i`.
+    Rectangular,
+    /// Complex numbers are shown as modulus and argument, like `5e^(0.9272952180016122i)`.
+    Polar,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AngleUnit {
+    /// A full turn is `2 * pi`.
+    #[default]
+    Radians,
+    /// A full turn is 360.
+    Degrees,
+    /// A full turn is 400.
+    Gradians,
+}
@@ -0,0 +1,125 @@
+use std::f64::consts::PI;
+
+// Lanczos approximation with g = 7 and 9 coefficients, accurate to about 15 digits
+pub(super) const LANCZOS_G: f64 = 7.0;
+pub(super) const LANCZOS_COEFFICIENTS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+// Floating point Gamma and related functions. Callers must rule out the poles at
+// zero and the negative integers.
+pub(super) fn gamma(x: f64) -> f64 {
+    if x.fract() == 0.0 && (1.0..=171.0).contains(&x) {
+        // Whole numbers are exact: Γ(n) = (n - 1)!
+        return (1..x as u64).map(|k| k as f64).product();
+    }
+    if x > 171.7 {
+        return f64::INFINITY; // Beyond the largest f64
+    }
+    if x < 0.5 {
+        // Reflection formula: Γ(x) Γ(1 - x) = π / sin(πx)
+        return PI / ((PI * x).sin() * gamma(1.0 - x));
+    }
+
+    let (sum, t) = lanczos(x);
+    // t^(x - 1/2) is split in halves so it can't overflow before e^-t scales it down
+    let half_power = t.powf((x - 0.5) / 2.0);
+    (2.0 * PI).sqrt() * sum * half_power * (half_power * (-t).exp())
+}
+
+// ln |Γ(x)|, which stays finite where Γ(x) overflows
+pub(super) fn ln_gamma(x: f64) -> f64 {
+    if x < 0.5 {
+        return (PI / (PI * x).sin().abs()).ln() - ln_gamma(1.0 - x);
+    }
+    let (sum, t) = lanczos(x);
+    (2.0 * PI).sqrt().ln() + sum.ln() + (x - 0.5) * t.ln() - t
+}
+
+// ψ(x) = Γ'(x) / Γ(x)
+pub(super) fn digamma(x: f64) -> f64 {
+    if x < 0.0 {
+        // Reflection formula: ψ(1 - x) - ψ(x) = π cot(πx)
+        return digamma(1.0 - x) - PI / (PI * x).tan();
+    }
+
+    // ψ(x) = ψ(x + 1) - 1/x moves x up to where the asymptotic series is accurate
+    let mut x = x;
+    let mut result = 0.0;
+    while x < 10.0 {
+        result -= 1.0 / x;
+        x += 1.0;
+    }
+    let inverse_square = 1.0 / (x * x);
+    let series = inverse_square
+        * (1.0 / 12.0
+            - inverse_square
+                * (1.0 / 120.0
+                    - inverse_square
+                        * (1.0 / 252.0 - inverse_square * (1.0 / 240.0 - inverse_square / 132.0))));
+    result + x.ln() - 0.5 / x - series
+}
+
+// x!! = 2^(x/2) (2/π)^((1 - cos πx) / 4) Γ(x/2 + 1), which is n!! at the whole numbers.
+// Callers must rule out the poles at the negative even numbers.
+pub(super) fn double_factorial(x: f64) -> f64 {
+    if x.fract() == 0.0 && x < 0.0 {
+        // Negative odd numbers step down from (-1)!! = 1 with x!! = (x + 2)!! / (x + 2)
+        let mut result = 1.0;
+        let mut m = -1.0;
+        while m > x && result != 0.0 {
+            result /= m;
+            m -= 2.0;
+        }
+        return result;
+    }
+    let weight = if x.fract() != 0.0 {
+        (1.0 - (PI * x).cos()) / 4.0
+    } else if x % 2.0 == 0.0 {
+        0.0
+    } else {
+        0.5
+    };
+    2f64.powf(x / 2.0) * (2.0 / PI).powf(weight) * gamma(x / 2.0 + 1.0)
+}
+
+// B(a, b) = Γ(a) Γ(b) / Γ(a + b)
+pub(super) fn beta(a: f64, b: f64) -> f64 {
+    let sum = a + b;
+    if sum <= 0.0 && sum.fract() == 0.0 {
+        return 0.0; // Γ(a + b) is infinite
+    }
+    let direct = gamma(a) * gamma(b) / gamma(sum);
+    if direct.is_finite() {
+        return direct;
+    }
+    let sign = gamma_sign(a) * gamma_sign(b) * gamma_sign(sum);
+    sign * (ln_gamma(a) + ln_gamma(b) - ln_gamma(sum)).exp()
+}
+
+// Γ(x) is negative between -1 and 0, -3 and -2, and so on
+fn gamma_sign(x: f64) -> f64 {
+    if x > 0.0 || x.floor() % 2.0 == 0.0 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+// The Lanczos sum for Γ(x) and the matching t = x + g - 1/2, for x >= 0.5
+fn lanczos(x: f64) -> (f64, f64) {
+    let x = x - 1.0;
+    let mut sum = LANCZOS_COEFFICIENTS[0];
+    for (i, coefficient) in LANCZOS_COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += coefficient / (x + i as f64);
+    }
+    (sum, x + LANCZOS_G + 0.5)
+}
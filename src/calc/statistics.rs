@@ -0,0 +1,64 @@
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+// Descriptive statistics on exact fractions. Floats and decimals convert to fractions without
+// rounding, so sums never lose digits and the variance has no cancellation, whatever the data;
+// the result is rounded once at the end. Callers must rule out empty lists.
+
+pub(super) fn mean(values: &[BigRational]) -> BigRational {
+    values.iter().sum::<BigRational>() / count(values.len())
+}
+
+// Mean squared deviation, divided by n - 1 for a sample and by n for a whole population.
+// Samples need at least two values.
+pub(super) fn variance(values: &[BigRational], sample: bool) -> BigRational {
+    let mean = mean(values);
+    let squares: BigRational = values
+        .iter()
+        .map(|value| {
+            let deviation = value - &mean;
+            &deviation * &deviation
+        })
+        .sum();
+    let divisor = if sample {
+        values.len() - 1
+    } else {
+        values.len()
+    };
+    squares / count(divisor)
+}
+
+// Linear interpolation between the closest ranks, with p from 0 to 100 like PERCENTILE.INC in
+// spreadsheets, so the 50th percentile is the median
+pub(super) fn percentile(sorted: &[BigRational], p: &BigRational) -> BigRational {
+    let rank = count(sorted.len() - 1) * p / count(100);
+    let lower = rank.floor();
+    let fraction = &rank - &lower;
+    let i = lower.to_integer().to_usize().unwrap();
+    if fraction.is_zero() {
+        sorted[i].clone()
+    } else {
+        &sorted[i] + (&sorted[i + 1] - &sorted[i]) * fraction
+    }
+}
+
+// The most frequent value, and the smallest one of those on a tie
+pub(super) fn mode(sorted: &[BigRational]) -> BigRational {
+    let mut best = (&sorted[0], 0);
+    let mut run = (&sorted[0], 0);
+    for value in sorted {
+        if *value == *run.0 {
+            run.1 += 1;
+        } else {
+            run = (value, 1);
+        }
+        if run.1 > best.1 {
+            best = run;
+        }
+    }
+    best.0.clone()
+}
+
+fn count(n: usize) -> BigRational {
+    BigRational::from_integer(n.into())
+}
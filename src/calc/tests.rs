@@ -1,4 +1,58 @@
-use crate::calc::calculator::evaluate;
+use std::cell::RefCell;
+
+use num_rational::BigRational;
+
+use crate::calc::calculator::Calculator;
+use crate::calc::error::{Error, ParseError};
+use crate::calc::settings::{AngleUnit, ComplexFormat, Format, Mode, Settings};
+use crate::calc::value::Value;
+
+// Each test thread shares one calculator, so state carries over between lines like in the REPL
+thread_local! {
+    static CALCULATOR: RefCell<Calculator> = RefCell::new(Calculator::new());
+}
+
+fn evaluate(line: String) -> Result<f64, Error> {
+    CALCULATOR.with(|calculator| {
+        calculator
+            .borrow_mut()
+            .eval(&line)
+            .map(|value| value.to_f64())
+    })
+}
+
+fn evaluate_statements(line: String) -> Result<Vec<f64>, Error> {
+    CALCULATOR.with(|calculator| {
+        let results = calculator.borrow_mut().eval_all(&line)?;
+        Ok(results.iter().map(Value::to_f64).collect())
+    })
+}
+
+fn function_definitions() -> Vec<String> {
+    CALCULATOR.with(|calculator| calculator.borrow().function_definitions())
+}
+
+fn remove_function(name: &str) -> bool {
+    CALCULATOR.with(|calculator| calculator.borrow_mut().remove_function(name))
+}
+
+fn math_error(reason: &str) -> Error {
+    Error::Math(reason.to_string())
+}
+
+// A fresh calculator with some settings changed from the defaults
+fn with_settings(change: impl FnOnce(&mut Settings)) -> Calculator {
+    let mut calculator = Calculator::new();
+    change(calculator.settings_mut());
+    calculator
+}
+
+fn syntax_error(input: &str) -> ParseError {
+    match evaluate(input.to_string()) {
+        Err(Error::Syntax(e)) => e,
+        other => panic!("expected syntax error for {:?}, got {:?}", input, other),
+    }
+}
 
 #[test]
 fn test_pi_constant() {
@@ -123,7 +177,7 @@ fn test_last_result_only_at_beginning() {
 fn test_nested_factorial() {
     assert_eq!(evaluate("(2!)!".to_string()).unwrap(), 2.0);
     assert_eq!(evaluate("(3!)!".to_string()).unwrap(), 720.0);
-    assert_eq!(evaluate("3!!".to_string()).unwrap(), 720.0);
+    assert_eq!(evaluate("3! !".to_string()).unwrap(), 720.0);
 }
 
 #[test]
@@ -134,8 +188,50 @@ fn test_division_by_zero() {
 
 #[test]
 fn test_factorial_edge_cases() {
-    assert!(evaluate("2.5!".to_string()).is_err());
+    assert!((evaluate("2.5!".to_string()).unwrap() - 3.323350970447843).abs() < 1e-12);
     assert!(evaluate("0!".to_string()).unwrap() == 1.0);
+    assert!(evaluate("(-3)!".to_string()).is_err());
+}
+
+#[test]
+fn test_gamma_functions() {
+    let approx = |input: &str, expected: f64| {
+        let result = evaluate(input.to_string()).unwrap();
+        assert!(
+            (result - expected).abs() < 1e-12 * expected.abs().max(1.0),
+            "{input} = {result}, expected {expected}"
+        );
+    };
+    approx("gamma(5)", 24.0);
+    approx("gamma(0.5)", std::f64::consts::PI.sqrt());
+    approx("gamma(-1.5)", 2.363271801207355);
+    approx("0.5!", 0.886226925452758);
+    approx("lgamma(100)", 359.1342053695754);
+    approx("lgamma(-2.5)", -0.05624371649767405);
+    approx("digamma(1)", -0.5772156649015329);
+    approx("digamma(-0.5)", 0.03648997397857652);
+    approx("beta(2, 3)", 1.0 / 12.0);
+    approx("beta(-0.5, 1.5)", -std::f64::consts::PI);
+    approx("beta(1.5, -1.5)", 0.0);
+}
+
+#[test]
+fn test_gamma_poles() {
+    let mut calculator = Calculator::new();
+    for input in [
+        "gamma(0)",
+        "gamma(-2)",
+        "lgamma(-1)",
+        "digamma(0)",
+        "(-1)!",
+        "beta(-3, 1)",
+    ] {
+        assert!(
+            matches!(calculator.eval(input), Err(Error::Domain(_))),
+            "{input} should be a domain error"
+        );
+    }
+    assert_eq!(exact("gamma(21)"), "2432902008176640000");
 }
 
 #[test]
@@ -224,3 +320,1479 @@ fn test_new_functions_complex() {
     assert_eq!(evaluate("ceil (sqrt 15)".to_string()).unwrap(), 4.0);
     assert_eq!(evaluate("round 2.5 + 1".to_string()).unwrap(), 4.0);
 }
+
+#[test]
+fn test_function_calls() {
+    assert_eq!(evaluate("log(100, 10)".to_string()).unwrap(), 2.0);
+    assert_eq!(evaluate("log(1000)".to_string()).unwrap(), 3.0);
+    assert_eq!(evaluate("log(8, 2) + 1".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("max(3, 7, 5)".to_string()).unwrap(), 7.0);
+    assert_eq!(evaluate("min(3, -7, 5)".to_string()).unwrap(), -7.0);
+    assert_eq!(evaluate("hypot(3, 4)".to_string()).unwrap(), 5.0);
+    assert_eq!(
+        evaluate("atan2(1, 1)".to_string()).unwrap(),
+        std::f64::consts::FRAC_PI_4
+    );
+    assert_eq!(evaluate("sqrt(16)".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("max(sqrt 16, 2 ^ 3)".to_string()).unwrap(), 8.0);
+    assert!(evaluate("max(1, sqrt(-1))".to_string()).is_err());
+    assert!(evaluate("min(2, ln 0)".to_string()).is_err());
+}
+
+#[test]
+fn test_function_call_arity() {
+    assert!(evaluate("log()".to_string()).is_err());
+    assert!(evaluate("log(1, 2, 3)".to_string()).is_err());
+    assert!(evaluate("atan2(1)".to_string()).is_err());
+    assert!(evaluate("sqrt(16, 2)".to_string()).is_err());
+    assert!(evaluate("max()".to_string()).is_err());
+    assert!(evaluate("foo(1)".to_string()).is_err());
+    assert!(evaluate("max(1, 2".to_string()).is_err());
+}
+
+#[test]
+fn test_parse_error_spans() {
+    assert_eq!(
+        syntax_error("2 + $"),
+        ParseError::UnexpectedChar {
+            found: '$',
+            span: 4..5
+        }
+    );
+    assert_eq!(
+        syntax_error("2 +"),
+        ParseError::UnexpectedEnd { span: 3..4 }
+    );
+    assert_eq!(
+        syntax_error("2 * (3 + 4"),
+        ParseError::UnbalancedParen { span: 4..5 }
+    );
+    assert_eq!(
+        syntax_error("2 + 3)"),
+        ParseError::UnbalancedParen { span: 5..6 }
+    );
+    assert_eq!(
+        syntax_error("2 3 4 "),
+        ParseError::TrailingInput { span: 2..5 }
+    );
+    assert_eq!(
+        syntax_error("1.2.3"),
+        ParseError::BadNumber {
+            text: "1.2.3".to_string(),
+            span: 0..5
+        }
+    );
+    assert!(matches!(
+        syntax_error("atan2(1)"),
+        ParseError::WrongArgumentCount { found: 1, span, .. } if span == (0..8)
+    ));
+}
+
+#[test]
+fn test_math_error() {
+    assert_eq!(
+        evaluate("sqrt -1".to_string()),
+        Err(math_error("square root of negative number encountered"))
+    );
+    // Reported where it happens, even if the value would have been dropped
+    assert_eq!(
+        evaluate("[1, 1/0][0]".to_string()),
+        Err(math_error("division by zero encountered"))
+    );
+    assert_eq!(evaluate("if(0, 1/0, 2)".to_string()), Ok(2.0));
+}
+
+#[test]
+fn test_scientific_notation() {
+    assert_eq!(evaluate("1e3".to_string()).unwrap(), 1000.0);
+    assert_eq!(evaluate("1.5e-3".to_string()).unwrap(), 0.0015);
+    assert_eq!(evaluate("6.022E23".to_string()).unwrap(), 6.022e23);
+    assert_eq!(evaluate("2.5e+2 * 2".to_string()).unwrap(), 500.0);
+    assert_eq!(evaluate("1e3 log 10".to_string()).unwrap(), 3.0);
+    assert_eq!(
+        evaluate("2 * e".to_string()).unwrap(),
+        2.0 * std::f64::consts::E
+    );
+    assert_eq!(evaluate("e ^ 0".to_string()).unwrap(), 1.0);
+    // An 'e' without exponent digits is not part of the number
+    assert!(evaluate("2e-".to_string()).is_err());
+}
+
+#[test]
+fn test_radix_literals() {
+    assert_eq!(evaluate("0xFF".to_string()).unwrap(), 255.0);
+    assert_eq!(evaluate("0x1f + 1".to_string()).unwrap(), 32.0);
+    assert_eq!(evaluate("0b1010_0101".to_string()).unwrap(), 165.0);
+    assert_eq!(evaluate("0o755".to_string()).unwrap(), 493.0);
+    assert_eq!(evaluate("1_000_000".to_string()).unwrap(), 1000000.0);
+    assert_eq!(evaluate("1_000.5".to_string()).unwrap(), 1000.5);
+}
+
+#[test]
+fn test_radix_literal_errors() {
+    assert_eq!(
+        syntax_error("0b102"),
+        ParseError::InvalidDigit {
+            digit: '2',
+            radix: 2,
+            span: 4..5
+        }
+    );
+    assert_eq!(
+        syntax_error("0o79"),
+        ParseError::InvalidDigit {
+            digit: '9',
+            radix: 8,
+            span: 3..4
+        }
+    );
+    assert!(matches!(
+        syntax_error("0xG1"),
+        ParseError::InvalidDigit { digit: 'G', .. }
+    ));
+    assert!(matches!(syntax_error("0x"), ParseError::BadNumber { .. }));
+    assert!(matches!(
+        syntax_error("1_000_"),
+        ParseError::BadNumber { .. }
+    ));
+    assert!(matches!(syntax_error("0x_1"), ParseError::BadNumber { .. }));
+}
+
+#[test]
+fn test_implicit_multiplication() {
+    let pi = std::f64::consts::PI;
+    assert_eq!(evaluate("2pi".to_string()).unwrap(), 2.0 * pi);
+    assert_eq!(
+        evaluate("2e".to_string()).unwrap(),
+        2.0 * std::f64::consts::E
+    );
+    assert_eq!(evaluate("3(4 + 5)".to_string()).unwrap(), 27.0);
+    assert_eq!(evaluate("(1 + 2)(3 + 4)".to_string()).unwrap(), 21.0);
+    assert_eq!(evaluate("2 sqrt 16".to_string()).unwrap(), 8.0);
+    assert_eq!(evaluate("2 max(1, 3)".to_string()).unwrap(), 6.0);
+}
+
+#[test]
+fn test_implicit_multiplication_precedence() {
+    let pi = std::f64::consts::PI;
+    // Binds tighter than explicit * and /
+    assert_eq!(evaluate("1 / 2pi".to_string()).unwrap(), 1.0 / (2.0 * pi));
+    assert_eq!(evaluate("6 / 2(1 + 2)".to_string()).unwrap(), 1.0);
+    // Binds looser than ^
+    assert_eq!(evaluate("2pi ^ 2".to_string()).unwrap(), 2.0 * pi * pi);
+    assert_eq!(evaluate("2 ^ 3(2)".to_string()).unwrap(), 16.0);
+    // Prefix functions take the whole implicit product
+    assert_eq!(evaluate("sin 2pi".to_string()).unwrap(), (2.0 * pi).sin());
+    assert_eq!(evaluate("sqrt 4pi".to_string()).unwrap(), (4.0 * pi).sqrt());
+    assert_eq!(evaluate("sqrt 16 * 2".to_string()).unwrap(), 8.0);
+    assert_eq!(evaluate("sin(2)pi".to_string()).unwrap(), 2f64.sin() * pi);
+    // Two bare numbers are never multiplied
+    assert!(evaluate("2 3".to_string()).is_err());
+    assert!(evaluate("(2)3".to_string()).is_err());
+}
+
+#[test]
+fn test_right_associative_power() {
+    assert_eq!(evaluate("2 ^ 3 ^ 2".to_string()).unwrap(), 512.0);
+    assert_eq!(evaluate("(2 ^ 3) ^ 2".to_string()).unwrap(), 64.0);
+    assert_eq!(evaluate("2 ^ 2 ^ 3 * 2".to_string()).unwrap(), 512.0);
+    assert_eq!(evaluate("8 - 2 - 1".to_string()).unwrap(), 5.0);
+    assert_eq!(evaluate("64 / 4 / 2".to_string()).unwrap(), 8.0);
+}
+
+#[test]
+fn test_unary_minus_precedence() {
+    assert_eq!(evaluate("0 + -2 ^ 2".to_string()).unwrap(), -4.0);
+    assert_eq!(evaluate("(-2) ^ 2".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("(-2 ^ 2)".to_string()).unwrap(), -4.0);
+    assert_eq!(evaluate("2 ^ -2".to_string()).unwrap(), 0.25);
+    assert_eq!(evaluate("1 - -3!".to_string()).unwrap(), 7.0);
+    assert_eq!(evaluate("3 * -2 + 1".to_string()).unwrap(), -5.0);
+    assert_eq!(evaluate("max(-1, -2)".to_string()).unwrap(), -1.0);
+}
+
+#[test]
+fn test_last_result_continues_expression() {
+    assert_eq!(evaluate("4".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("* 2 + 1".to_string()).unwrap(), 9.0);
+    assert_eq!(evaluate("- 2 ^ 2".to_string()).unwrap(), 5.0);
+}
+
+#[test]
+fn test_unicode_operators() {
+    assert_eq!(evaluate("3 × 4".to_string()).unwrap(), 12.0);
+    assert_eq!(evaluate("3 · 4".to_string()).unwrap(), 12.0);
+    assert_eq!(evaluate("12 ÷ 4".to_string()).unwrap(), 3.0);
+    assert_eq!(evaluate("5 − 7".to_string()).unwrap(), -2.0);
+    assert_eq!(evaluate("2 × −3".to_string()).unwrap(), -6.0);
+    assert_eq!(evaluate("√16".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("√(9) + 1".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("2√9".to_string()).unwrap(), 6.0);
+    assert_eq!(evaluate("π".to_string()).unwrap(), std::f64::consts::PI);
+    assert_eq!(
+        evaluate("2π".to_string()).unwrap(),
+        2.0 * std::f64::consts::PI
+    );
+}
+
+#[test]
+fn test_unicode_superscripts_and_fractions() {
+    assert_eq!(evaluate("3²".to_string()).unwrap(), 9.0);
+    assert_eq!(evaluate("2³ + 1".to_string()).unwrap(), 9.0);
+    assert_eq!(evaluate("2¹⁰".to_string()).unwrap(), 1024.0);
+    assert_eq!(evaluate("2⁻¹".to_string()).unwrap(), 0.5);
+    assert_eq!(evaluate("0 − 3²".to_string()).unwrap(), -9.0);
+    assert_eq!(evaluate("½".to_string()).unwrap(), 0.5);
+    assert_eq!(evaluate("¾ × 8".to_string()).unwrap(), 6.0);
+    assert_eq!(evaluate("1½".to_string()).unwrap(), 1.5);
+    assert_eq!(evaluate("4(½)".to_string()).unwrap(), 2.0);
+    assert!(evaluate("2⁻".to_string()).is_err());
+}
+
+#[test]
+fn test_comparison_operators() {
+    assert_eq!(evaluate("5 > 3".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("5 < 3".to_string()).unwrap(), 0.0);
+    assert_eq!(evaluate("3 >= 3".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("2 <= 1".to_string()).unwrap(), 0.0);
+    assert_eq!(evaluate("2 + 2 == 4".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("2 != 2".to_string()).unwrap(), 0.0);
+    assert_eq!(evaluate("2 ≤ 3".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("2 ≥ 3".to_string()).unwrap(), 0.0);
+    assert_eq!(evaluate("2 ≠ 3".to_string()).unwrap(), 1.0);
+}
+
+#[test]
+fn test_boolean_operators() {
+    assert_eq!(evaluate("1 < 2 && 2 < 3".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("1 > 2 || 2 > 3".to_string()).unwrap(), 0.0);
+    assert_eq!(evaluate("0 || 5".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("1 || 0 && 0".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("!0".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("!(2 > 1)".to_string()).unwrap(), 0.0);
+    // Short-circuiting skips the division by zero
+    assert_eq!(evaluate("0 && 1 / 0".to_string()).unwrap(), 0.0);
+    assert_eq!(evaluate("1 || 1 / 0".to_string()).unwrap(), 1.0);
+}
+
+#[test]
+fn test_factorial_vs_not() {
+    assert_eq!(evaluate("3! == 6".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("3 != 6".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("3!=6".to_string()).unwrap(), 1.0); // not-equal, not factorial
+    assert_eq!(evaluate("!3!".to_string()).unwrap(), 0.0);
+}
+
+#[test]
+fn test_conditional() {
+    assert_eq!(evaluate("if(2 > 1, 10, 20)".to_string()).unwrap(), 10.0);
+    assert_eq!(evaluate("if(0, 10, 20) + 1".to_string()).unwrap(), 21.0);
+    assert_eq!(evaluate("if(1, 5, 1 / 0)".to_string()).unwrap(), 5.0);
+    assert!(evaluate("if(0, 5, 1 / 0)".to_string()).is_err());
+    assert!(evaluate("if(1, 2)".to_string()).is_err());
+}
+
+#[test]
+fn test_comparison_propagates_errors() {
+    assert!(evaluate("sqrt -1 > 0".to_string()).is_err());
+    assert!(evaluate("1 && ln 0".to_string()).is_err());
+    assert!(evaluate("!(1 / 0)".to_string()).is_err());
+}
+
+#[test]
+fn test_multiple_statements() {
+    assert_eq!(
+        evaluate_statements("1 + 1; 2 * 3; 10".to_string()).unwrap(),
+        vec![2.0, 6.0, 10.0]
+    );
+    assert_eq!(evaluate("1 + 1; 2 * 3".to_string()).unwrap(), 6.0);
+    assert_eq!(evaluate("2 + 3; * 4".to_string()).unwrap(), 20.0);
+    assert_eq!(evaluate("5;; 6;".to_string()).unwrap(), 6.0);
+    assert_eq!(evaluate("9; sqrt; - 1".to_string()).unwrap(), 2.0);
+    assert!(evaluate_statements(";".to_string()).unwrap().is_empty());
+    assert!(evaluate(";".to_string()).is_err());
+    assert!(evaluate("1; 2 +; 3".to_string()).is_err());
+}
+
+#[test]
+fn test_comments() {
+    assert_eq!(
+        evaluate("pi * 3 ^ 2 # area".to_string()).unwrap(),
+        std::f64::consts::PI * 9.0
+    );
+    assert_eq!(evaluate("1 # first\n+ 2".to_string()).unwrap(), 3.0);
+    assert_eq!(evaluate("2 # a; 3".to_string()).unwrap(), 2.0);
+    assert_eq!(
+        evaluate_statements("1 # first\n2 + 3".to_string()).unwrap(),
+        vec![1.0, 5.0]
+    );
+    assert_eq!(evaluate("# note".to_string()), Err(Error::NoResult));
+    assert_eq!(
+        syntax_error("1 +\n2"),
+        ParseError::UnexpectedEnd { span: 3..4 }
+    );
+    assert!(
+        evaluate_statements("# only a comment".to_string())
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        syntax_error("2 3; 4"),
+        ParseError::TrailingInput { span: 2..3 }
+    );
+    assert_eq!(
+        syntax_error("2 3 # note"),
+        ParseError::TrailingInput { span: 2..3 }
+    );
+}
+
+#[test]
+fn test_variables() {
+    assert_eq!(evaluate("rate = 0.5".to_string()).unwrap(), 0.5);
+    assert_eq!(
+        evaluate("1000 * (1 + rate) ^ 2".to_string()).unwrap(),
+        2250.0
+    );
+    assert_eq!(evaluate("r = 3; r ^ 2".to_string()).unwrap(), 9.0);
+    assert_eq!(evaluate("2r + 1".to_string()).unwrap(), 7.0);
+    assert_eq!(evaluate("r = r + 1; r".to_string()).unwrap(), 4.0);
+    assert_eq!(evaluate("x1 = 2; x1 == 2".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("logx = 5; logx".to_string()).unwrap(), 5.0);
+}
+
+#[test]
+fn test_unknown_variable() {
+    assert_eq!(
+        evaluate("undefined + 1".to_string()),
+        Err(Error::UnknownVariable("undefined".to_string()))
+    );
+    // A failed assignment does not define the variable
+    assert!(evaluate("y = sqrt -1".to_string()).is_err());
+    assert_eq!(
+        evaluate("y".to_string()),
+        Err(Error::UnknownVariable("y".to_string()))
+    );
+}
+
+#[test]
+fn test_reserved_names() {
+    assert_eq!(
+        syntax_error("pi = 3"),
+        ParseError::ReservedName {
+            name: "pi".to_string(),
+            span: 0..2
+        }
+    );
+    assert!(matches!(
+        syntax_error("sin = 1"),
+        ParseError::ReservedName { .. }
+    ));
+    assert!(matches!(
+        syntax_error("max = 1"),
+        ParseError::ReservedName { .. }
+    ));
+    assert!(matches!(
+        syntax_error("e=2"),
+        ParseError::ReservedName { .. }
+    ));
+    // i is only the imaginary unit with complex numbers enabled
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("i = 3; i^2"), Ok(integer(9)));
+    assert_eq!(calculator.eval("sum(i, 1, 3, i)"), Ok(integer(6)));
+    let mut calculator =
+        with_settings(|settings| settings.complex = Some(ComplexFormat::Rectangular));
+    assert!(matches!(
+        calculator.eval("i = 3"),
+        Err(Error::Syntax(ParseError::ReservedName { .. }))
+    ));
+}
+
+#[test]
+fn test_user_functions() {
+    assert!(
+        evaluate_statements("f(x) = x^2 + 1".to_string())
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(evaluate("f(3)".to_string()).unwrap(), 10.0);
+    assert_eq!(evaluate("2f(1) + f(0)".to_string()).unwrap(), 5.0);
+    assert_eq!(evaluate("h(x) = x".to_string()), Err(Error::NoResult));
+    assert_eq!(
+        evaluate("bmi(w, h) = w / h^2; bmi(81, 1.8)".to_string()).unwrap(),
+        25.0
+    );
+    assert_eq!(evaluate("k() = 42; k() + 1".to_string()).unwrap(), 43.0);
+    assert_eq!(evaluate("g(x) = f(x) * 2; g(2)".to_string()).unwrap(), 10.0);
+    // Redefining replaces the previous definition
+    assert_eq!(evaluate("f(x) = x; f(3)".to_string()).unwrap(), 3.0);
+}
+
+#[test]
+fn test_user_function_scoping() {
+    assert_eq!(
+        evaluate("x = 10; y = 1; f(x) = x + y; f(2)".to_string()).unwrap(),
+        3.0
+    );
+    // Parameters do not leak into the global variables
+    assert_eq!(evaluate("x".to_string()).unwrap(), 10.0);
+    // Callee parameters are not visible from other functions it calls
+    assert_eq!(
+        evaluate("h() = y; g(y) = h(); g(5)".to_string()).unwrap(),
+        1.0
+    );
+    assert_eq!(
+        evaluate("u(a) = b; u(1)".to_string()),
+        Err(Error::UnknownVariable("b".to_string()))
+    );
+}
+
+#[test]
+fn test_recursive_user_functions() {
+    assert_eq!(
+        evaluate("fact(n) = if(n <= 1, 1, n * fact(n - 1)); fact(10)".to_string()).unwrap(),
+        3628800.0
+    );
+    assert_eq!(
+        evaluate("loop(n) = loop(n + 1); loop(0)".to_string()),
+        Err(Error::RecursionLimit)
+    );
+}
+
+#[test]
+fn test_user_function_errors() {
+    evaluate_statements("f(x, y) = x + y".to_string()).unwrap();
+    assert!(matches!(
+        syntax_error("f(1)"),
+        ParseError::WrongArgumentCount { found: 1, .. }
+    ));
+    assert_eq!(
+        syntax_error("sin(x) = x"),
+        ParseError::ReservedName {
+            name: "sin".to_string(),
+            span: 0..3
+        }
+    );
+    assert!(matches!(
+        syntax_error("q(pi) = 1"),
+        ParseError::ReservedName { .. }
+    ));
+    assert_eq!(
+        syntax_error("q(a, a) = a"),
+        ParseError::DuplicateParameter {
+            name: "a".to_string(),
+            span: 5..6
+        }
+    );
+    // Other names before '(' are variables multiplying it
+    assert_eq!(
+        evaluate("nope(1)".to_string()),
+        Err(Error::UnknownVariable("nope".to_string()))
+    );
+    assert_eq!(evaluate("x = 5; x(2)".to_string()), Ok(10.0));
+    assert_eq!(evaluate("q(x) = x(x + 1); q(3)".to_string()), Ok(12.0));
+}
+
+#[test]
+fn test_listing_and_removing_functions() {
+    evaluate_statements("sq(x) = x * x; cube(x) = x^3 # comment".to_string()).unwrap();
+    assert_eq!(
+        function_definitions(),
+        vec!["cube(x) = x^3", "sq(x) = x * x"]
+    );
+
+    evaluate_statements("twice(x) = 2sq(x)".to_string()).unwrap();
+    assert!(remove_function("sq"));
+    assert!(!remove_function("sq"));
+    assert_eq!(
+        evaluate("twice(2)".to_string()),
+        Err(Error::UnknownFunction("sq".to_string()))
+    );
+}
+
+#[test]
+fn test_calculator_api() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("x = 4; sqrt x").unwrap(), integer(2));
+    assert_eq!(calculator.last_result(), Some(integer(2)));
+    assert_eq!(calculator.variable("x"), Some(integer(4)));
+
+    calculator.set_variable("y", 0.5);
+    assert_eq!(
+        calculator.eval_all("y * 2; f(a) = a; f(3)").unwrap(),
+        vec![Value::Number(1.0), integer(3)]
+    );
+    assert_eq!(calculator.parse("1 + 2").unwrap().len(), 1);
+
+    // State is owned by each calculator, not shared
+    assert_eq!(
+        Calculator::new().eval("x"),
+        Err(Error::UnknownVariable("x".to_string()))
+    );
+}
+
+#[test]
+fn test_calculator_is_send() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let mut calculator = Calculator::new();
+    calculator.eval_all("f(x) = x + 1").unwrap();
+    assert_send(&calculator);
+
+    let result = std::thread::spawn(move || calculator.eval("f(1)").unwrap())
+        .join()
+        .unwrap();
+    assert_eq!(result, integer(2));
+}
+
+#[test]
+fn test_value_display() {
+    assert_eq!(Value::Number(5.0).to_string(), "5");
+    assert_eq!(Value::Number(-2.5).to_string(), "-2.5");
+}
+
+fn integer(n: i64) -> Value {
+    Value::Integer(n.into())
+}
+
+fn exact(input: &str) -> String {
+    let value = Calculator::new().eval(input).unwrap();
+    assert!(matches!(value, Value::Integer(_)), "{input} gave {value:?}");
+    value.to_string()
+}
+
+#[test]
+fn test_big_integers_are_exact() {
+    assert_eq!(exact("30!"), "265252859812191058636308480000000");
+    assert_eq!(
+        exact("2^200"),
+        "1606938044258990275541962092341162602522202993782792835301376"
+    );
+    assert_eq!(exact("2^64 + 1 - 2^64"), "1");
+    assert_eq!(exact("(2^100 + 7) % 10"), "3");
+    assert_eq!(exact("25! / 24!"), "25");
+    assert_eq!(
+        exact("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF + 1"),
+        "340282366920938463463374607431768211456"
+    );
+    assert_eq!(exact("sqrt(2^100)"), "1125899906842624");
+    assert_eq!(exact("max(2^70, 3^44)"), "1180591620717411303424");
+    assert_eq!(exact("2^70 > 2^70 - 1"), "1");
+}
+
+#[test]
+fn test_big_integers_promote_to_float() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("7 / 2").unwrap(), Value::Number(3.5));
+    assert_eq!(calculator.eval("2^-1").unwrap(), Value::Number(0.5));
+    assert_eq!(
+        calculator.eval("30! * 0.5").unwrap(),
+        Value::Number(1.3262642990609553e32)
+    );
+    assert_eq!(
+        calculator.eval("sqrt 2").unwrap(),
+        Value::Number(2f64.sqrt())
+    );
+    assert_eq!(calculator.eval("200!").unwrap().to_f64(), f64::INFINITY);
+}
+
+#[test]
+fn test_exact_results_beyond_max_bits() {
+    let too_large = Err(Error::Domain("result is too large".to_string()));
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("2^2^30"), too_large);
+    assert_eq!(calculator.eval("2^100000000"), too_large);
+    assert_eq!(calculator.eval("100000!"), too_large);
+    assert_eq!(calculator.eval("(10^400)!"), too_large);
+    assert_eq!(calculator.eval("gamma(10^7)"), too_large);
+    assert!(matches!(
+        calculator.eval("2^1000000").unwrap(),
+        Value::Integer(_)
+    ));
+    // Floating point can't hold them either
+    assert_eq!(calculator.eval("2^2000 / 3"), too_large);
+    assert_eq!(calculator.eval("2^2000 + 0.5"), too_large);
+    assert_eq!(calculator.eval("(200!) * 1.5"), too_large);
+    assert_eq!(calculator.eval("2^1023 * 2.5"), too_large);
+    assert!(calculator.eval("2^1000 * 0.5").is_ok());
+}
+
+fn fraction(numerator: i64, denominator: i64) -> Value {
+    Value::Rational(BigRational::new(numerator.into(), denominator.into()))
+}
+
+#[test]
+fn test_rational_arithmetic_is_exact() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Rational);
+    assert_eq!(calculator.eval("0.1 + 0.2").unwrap(), fraction(3, 10));
+    assert_eq!(calculator.eval("1/3 * 3").unwrap(), fraction(1, 1));
+    assert_eq!(calculator.eval("(2/3)^-2").unwrap(), fraction(9, 4));
+    assert_eq!(calculator.eval("1.5e-3 - ½").unwrap(), fraction(-997, 2000));
+    assert_eq!(calculator.eval("7.5 % 2").unwrap(), fraction(3, 2));
+    assert_eq!(calculator.eval("sqrt(4/9)").unwrap(), fraction(2, 3));
+    assert_eq!(calculator.eval("max(1/3, 0.3)").unwrap(), fraction(1, 3));
+    assert_eq!(calculator.eval("0.1 + 0.2 == 0.3").unwrap(), fraction(1, 1));
+}
+
+#[test]
+fn test_rational_falls_back_to_float() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Rational);
+    assert_eq!(
+        calculator.eval("sqrt 2").unwrap(),
+        Value::Number(2f64.sqrt())
+    );
+    assert_eq!(calculator.eval("1/2 + sin 0").unwrap(), Value::Number(0.5));
+    assert_eq!(calculator.eval("4^0.5").unwrap(), Value::Number(2.0));
+    assert_eq!(
+        calculator.eval("1/0"),
+        Err(math_error("division by zero encountered"))
+    );
+}
+
+#[test]
+fn test_rational_format() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Rational);
+    let value = calculator.eval("x = 1/4; x + 0.05").unwrap();
+    assert_eq!(calculator.format(&value), "3/10");
+    assert_eq!(calculator.variable("x"), Some(fraction(1, 4)));
+
+    calculator.settings_mut().format = Format::Decimal;
+    assert_eq!(calculator.format(&value), "0.3");
+    assert_eq!(calculator.format(&fraction(6, 3)), "2");
+}
+
+fn decimal(calculator: &mut Calculator, input: &str) -> String {
+    let value = calculator.eval(input).unwrap();
+    assert!(matches!(value, Value::Decimal(_)), "{input} gave {value:?}");
+    calculator.format(&value)
+}
+
+#[test]
+fn test_decimal_constants() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 50 });
+    assert_eq!(
+        decimal(&mut calculator, "pi"),
+        "3.1415926535897932384626433832795028841971693993751"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "e"),
+        "2.7182818284590452353602874713526624977572470937" // the 50th digit is 0
+    );
+}
+
+#[test]
+fn test_decimal_arithmetic() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 30 });
+    assert_eq!(decimal(&mut calculator, "0.1 + 0.2"), "0.3");
+    assert_eq!(
+        decimal(&mut calculator, "1/3"),
+        "0.333333333333333333333333333333"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "2/3"),
+        "0.666666666666666666666666666667"
+    );
+    assert_eq!(decimal(&mut calculator, "2^-3"), "0.125");
+    assert_eq!(decimal(&mut calculator, "(-7.5) % 2"), "-1.5");
+    assert_eq!(
+        decimal(&mut calculator, "25!"),
+        "15511210043330985984000000"
+    );
+    assert_eq!(decimal(&mut calculator, "round 2.5; floor -2.5"), "-3");
+    assert_eq!(
+        decimal(&mut calculator, "max(1/3, 0.3)"),
+        "0.333333333333333333333333333333"
+    );
+    assert_eq!(decimal(&mut calculator, "1e-40 + 1 > 1"), "0");
+    assert_eq!(decimal(&mut calculator, "1e-40 > 0"), "1");
+}
+
+#[test]
+fn test_decimal_functions() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 40 });
+    assert_eq!(
+        decimal(&mut calculator, "sqrt 2"),
+        "1.41421356237309504880168872420969807857"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "ln 2"),
+        "0.6931471805599453094172321214581765680755"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "2 ^ 0.5"),
+        "1.41421356237309504880168872420969807857"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "sin 1"),
+        "0.8414709848078965066525023216302989996226"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "cos 1"),
+        "0.5403023058681397174009366074429766037323"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "atan2(1, 1) * 4"),
+        "3.141592653589793238462643383279502884197"
+    );
+    assert_eq!(decimal(&mut calculator, "log(1000)"), "3");
+    assert_eq!(decimal(&mut calculator, "hypot(3, 4)"), "5");
+}
+
+#[test]
+fn test_decimal_domain_errors() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 20 });
+    assert_eq!(
+        calculator.eval("sqrt(-1)"),
+        Err(math_error("square root of negative number encountered"))
+    );
+    assert_eq!(
+        calculator.eval("ln 0"),
+        Err(math_error(
+            "natural logarithm of non-positive number encountered"
+        ))
+    );
+    assert_eq!(
+        calculator.eval("1 / 0"),
+        Err(math_error("division by zero encountered"))
+    );
+    assert!(matches!(calculator.eval("(-3)!"), Err(Error::Domain(_))));
+    assert!(matches!(calculator.eval("gamma(0)"), Err(Error::Domain(_))));
+    let too_large = Err(Error::Domain("result is too large".to_string()));
+    assert_eq!(calculator.eval("10^(10^10)"), too_large);
+    assert_eq!(calculator.eval("(10^6)!"), too_large);
+    assert_eq!(
+        calculator.eval("0.1^(10^10)"),
+        Err(Error::Domain("result is too small".to_string()))
+    );
+    // Reducing them would take pi to thousands of digits
+    assert_eq!(
+        calculator.eval("sin(1e5000)"),
+        Err(Error::Domain("sin argument is too large".to_string()))
+    );
+    assert_eq!(
+        calculator.eval("cos(2^300000)"),
+        Err(Error::Domain("cos argument is too large".to_string()))
+    );
+    assert!(calculator.eval("sin(1e300)").is_ok());
+}
+
+#[test]
+fn test_decimal_scientific_notation() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 20 });
+    assert_eq!(decimal(&mut calculator, "10^60"), "1e60");
+    assert_eq!(decimal(&mut calculator, "(-1.5) * 10^-45"), "-1.5e-45");
+    assert_eq!(
+        decimal(&mut calculator, "2^1000"),
+        "1.0715086071862673209e301"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "10^30"),
+        "1000000000000000000000000000000"
+    );
+}
+
+#[test]
+fn test_decimal_gamma() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 40 });
+    assert_eq!(
+        decimal(&mut calculator, "gamma(0.5)"),
+        "1.772453850905516027298167483341145182798"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "2.5!"),
+        "3.323350970447842551184064031264647217745"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "lgamma(0.5)"),
+        "0.5723649429247000870717136756765293558236"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "digamma(1)"),
+        "-0.5772156649015328606065120900824024310422"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "beta(0.5, 0.5)"),
+        "3.141592653589793238462643383279502884197"
+    );
+    assert_eq!(decimal(&mut calculator, "gamma(6)"), "120");
+    // Large whole numbers go through Stirling's series instead of a product
+    assert_eq!(
+        decimal(&mut calculator, "20000!"),
+        "1.819206320230345134827641756866458766072e77337"
+    );
+    let too_large = Err(Error::Domain("result is too large".to_string()));
+    assert_eq!(calculator.eval("gamma(10^7)"), too_large);
+    assert_eq!(calculator.eval("gamma(2^64)"), too_large);
+}
+
+fn complex(calculator: &mut Calculator, input: &str) -> String {
+    let value = calculator.eval(input).unwrap();
+    calculator.format(&value)
+}
+
+#[test]
+fn test_complex_arithmetic() {
+    let mut calculator =
+        with_settings(|settings| settings.complex = Some(ComplexFormat::Rectangular));
+    assert_eq!(complex(&mut calculator, "sqrt(-4)"), "2i");
+    assert_eq!(complex(&mut calculator, "ln(-1)"), "3.141592653589793i");
+    assert_eq!(complex(&mut calculator, "(1 + 2i) * (3 - i)"), "5 + 5i");
+    assert_eq!(complex(&mut calculator, "(3 + 4i) / (1 - 2i)"), "-1 + 2i");
+    assert_eq!(complex(&mut calculator, "(-i)"), "-i");
+    assert_eq!(
+        complex(&mut calculator, "(-8)^(1/3)"),
+        "1 + 1.732050807568877i"
+    );
+    assert_eq!(
+        complex(&mut calculator, "log(-100)"),
+        "2 + 1.3643763538418412i"
+    );
+    // Results without an imaginary part are real again
+    assert_eq!(calculator.eval("i^2"), Ok(Value::Number(-1.0)));
+    assert_eq!(calculator.eval("z = 3 + 4i; abs z"), Ok(Value::Number(5.0)));
+    assert_eq!(calculator.eval("z == conj(conj z)"), Ok(Value::Number(1.0)));
+}
+
+#[test]
+fn test_complex_functions() {
+    let mut calculator =
+        with_settings(|settings| settings.complex = Some(ComplexFormat::Rectangular));
+    assert_eq!(calculator.eval("re(3 + 4i)"), Ok(Value::Number(3.0)));
+    assert_eq!(calculator.eval("im(3 + 4i)"), Ok(Value::Number(4.0)));
+    assert_eq!(complex(&mut calculator, "conj(3 + 4i)"), "3 - 4i");
+    assert_eq!(complex(&mut calculator, "arg(-1)"), "3.141592653589793");
+    assert_eq!(complex(&mut calculator, "arg(i)"), "1.5707963267948966");
+    assert_eq!(exact("re 7"), "7");
+    assert_eq!(exact("im 7"), "0");
+
+    let Ok(Value::Complex(z)) = calculator.eval("gamma(1 + i)") else {
+        panic!("gamma(1 + i) should be complex");
+    };
+    assert!((z.re - 0.498_015_668_118_356).abs() < 1e-12);
+    assert!((z.im + 0.154_949_828_301_810_7).abs() < 1e-12);
+
+    // Complex numbers have no order
+    assert_eq!(
+        calculator.eval("i < 1"),
+        Err(math_error("complex numbers cannot be compared"))
+    );
+    assert_eq!(
+        calculator.eval("max(i, 1)"),
+        Err(math_error("complex numbers cannot be compared"))
+    );
+}
+
+#[test]
+fn test_complex_polar_format() {
+    let mut calculator = with_settings(|settings| settings.complex = Some(ComplexFormat::Polar));
+    assert_eq!(
+        complex(&mut calculator, "3 + 4i"),
+        "5e^(0.9272952180016122i)"
+    );
+    assert_eq!(complex(&mut calculator, "2i"), "2e^(1.5707963267948966i)");
+}
+
+#[test]
+fn test_real_mode_rejects_complex() {
+    let mut calculator = Calculator::new();
+    assert_eq!(
+        calculator.eval("sqrt(-4)"),
+        Err(math_error("square root of negative number encountered"))
+    );
+    assert_eq!(
+        calculator.eval("ln(-1)"),
+        Err(math_error(
+            "natural logarithm of non-positive number encountered"
+        ))
+    );
+    assert_eq!(
+        calculator.eval("(-8)^(1/3)"),
+        Err(math_error(
+            "fractional power of negative number encountered"
+        ))
+    );
+    assert!(matches!(calculator.eval("2i"), Err(Error::Domain(_))));
+}
+
+#[test]
+fn test_angle_mode() {
+    let mut calculator = with_settings(|settings| settings.angle = AngleUnit::Degrees);
+    assert_eq!(calculator.eval("sin 90"), Ok(integer(1)));
+    assert_eq!(calculator.eval("cos 90"), Ok(integer(0)));
+    assert_eq!(calculator.eval("sin 30"), Ok(Value::Number(0.5)));
+    assert_eq!(calculator.eval("cos(-240)"), Ok(Value::Number(-0.5)));
+    assert_eq!(calculator.eval("tan 135"), Ok(integer(-1)));
+    assert!(matches!(calculator.eval("tan 270"), Err(Error::Domain(_))));
+    assert_eq!(calculator.eval("atan2(1, 1)"), Ok(Value::Number(45.0)));
+
+    let mut calculator = with_settings(|settings| settings.angle = AngleUnit::Gradians);
+    assert_eq!(calculator.eval("sin 100"), Ok(integer(1)));
+    assert_eq!(calculator.eval("cos 200"), Ok(integer(-1)));
+
+    // Radians stay the default
+    assert!((evaluate("sin 90".to_string()).unwrap() - 0.8939966636005579).abs() < 1e-15);
+}
+
+#[test]
+fn test_angle_suffixes() {
+    assert_eq!(evaluate("sin 90°".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("sin(90deg)".to_string()).unwrap(), 1.0);
+    assert_eq!(evaluate("180°".to_string()).unwrap(), std::f64::consts::PI);
+    assert_eq!(evaluate("sin 100grad".to_string()).unwrap(), 1.0);
+
+    // A suffix overrides the angle mode
+    let mut calculator = with_settings(|settings| settings.angle = AngleUnit::Degrees);
+    assert_eq!(calculator.eval("100grad"), Ok(integer(90)));
+    assert_eq!(calculator.eval("sin(pi / 2 * 1rad)"), Ok(integer(1)));
+    assert_eq!(calculator.eval("(45 + 45)°"), Ok(integer(90)));
+    // The unit words are reserved, so they always mean the unit
+    assert!(matches!(
+        syntax_error("deg = 2"),
+        ParseError::ReservedName { .. }
+    ));
+    assert!(matches!(
+        syntax_error("f(rad) = rad"),
+        ParseError::ReservedName { .. }
+    ));
+    assert_eq!(
+        calculator.eval("3 deg"),
+        Err(Error::UnknownVariable("deg".to_string()))
+    );
+}
+
+#[test]
+fn test_decimal_angle_mode() {
+    let mut calculator = with_settings(|settings| {
+        settings.mode = Mode::Decimal { digits: 30 };
+        settings.angle = AngleUnit::Degrees;
+    });
+    assert_eq!(decimal(&mut calculator, "atan2(1, 1)"), "45");
+    assert_eq!(decimal(&mut calculator, "cos 60"), "0.5");
+    assert_eq!(
+        decimal(&mut calculator, "sin 45"),
+        "0.707106781186547524400844362105"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "1rad"),
+        "57.2957795130823208767981548141"
+    );
+}
+
+#[test]
+fn test_inverse_and_hyperbolic_trig() {
+    let approx = |input: &str, expected: f64| {
+        let result = evaluate(input.to_string()).unwrap();
+        assert!(
+            (result - expected).abs() < 1e-12,
+            "{input} = {result}, expected {expected}"
+        );
+    };
+    approx("asin 1", std::f64::consts::FRAC_PI_2);
+    approx("acos 0.5", std::f64::consts::FRAC_PI_3);
+    approx("atan 1", std::f64::consts::FRAC_PI_4);
+    approx("sec 1", 1.8508157176809257);
+    approx("csc 1", 1.1883951057781212);
+    approx("cot 1", 0.6420926159343306);
+    approx("sinh 1", 1.1752011936438014);
+    approx("cosh 1", 1.5430806348152437);
+    approx("tanh 1", 0.7615941559557649);
+    approx("asinh 1", 0.881373587019543);
+    approx("acosh 2", 1.3169578969248166);
+    approx("atanh 0.5", 0.5493061443340548);
+}
+
+#[test]
+fn test_inverse_trig_domains() {
+    for input in [
+        "asin 2",
+        "acos(-1.5)",
+        "acosh 0.5",
+        "atanh 1",
+        "csc 0",
+        "cot 0",
+    ] {
+        assert!(
+            matches!(evaluate(input.to_string()), Err(Error::Domain(_))),
+            "{input} should fail"
+        );
+    }
+    let mut calculator = Calculator::new();
+    assert_eq!(
+        calculator.eval("asin 2"),
+        Err(Error::Domain("asin is undefined at 2".to_string()))
+    );
+    // Rounding keeps pi/2 from reaching the pole
+    for input in ["tan(pi/2)", "sec(-pi/2)", "cot(pi)", "csc(2pi)"] {
+        assert!(
+            matches!(calculator.eval(input), Err(Error::Domain(_))),
+            "{input} should fail"
+        );
+    }
+    assert!(calculator.eval("tan 1.57").is_ok());
+
+    // Complex numbers take over where the real functions are undefined
+    let mut calculator =
+        with_settings(|settings| settings.complex = Some(ComplexFormat::Rectangular));
+    assert_eq!(complex(&mut calculator, "acosh 0.5"), "1.0471975511965979i");
+    assert!(matches!(calculator.eval("asin 2"), Ok(Value::Complex(_))));
+    assert_eq!(
+        calculator.eval("atanh 1"),
+        Err(Error::Domain("atanh is undefined at 1".to_string()))
+    );
+}
+
+#[test]
+fn test_inverse_trig_angle_mode() {
+    let mut calculator = with_settings(|settings| settings.angle = AngleUnit::Degrees);
+    assert_eq!(calculator.eval("asin 0.5"), Ok(integer(30)));
+    assert_eq!(calculator.eval("acos(-0.5)"), Ok(integer(120)));
+    assert_eq!(calculator.eval("atan(-1)"), Ok(integer(-45)));
+    assert_eq!(calculator.eval("sec 60"), Ok(integer(2)));
+    assert_eq!(calculator.eval("cot 90"), Ok(integer(0)));
+    assert!(matches!(calculator.eval("sec 90"), Err(Error::Domain(_))));
+    let Ok(Value::Number(angle)) = calculator.eval("asin 0.3") else {
+        panic!("asin 0.3 should be a number");
+    };
+    assert!((angle - 17.457603123722095).abs() < 1e-12);
+
+    let mut calculator = with_settings(|settings| settings.angle = AngleUnit::Gradians);
+    assert_eq!(calculator.eval("acos 0"), Ok(integer(100)));
+}
+
+#[test]
+fn test_decimal_inverse_and_hyperbolic_trig() {
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 30 });
+    assert_eq!(
+        decimal(&mut calculator, "acos 0.3"),
+        "1.26610367277949911125931873041"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "sinh 2"),
+        "3.6268604078470187676682139828"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "acosh 1.0000000001"),
+        "0.0000141421356236130993578217809718"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "atanh 0.9"),
+        "1.47221948958322023000451371594"
+    );
+    assert_eq!(
+        decimal(&mut calculator, "sinh 1e-20"),
+        "0.00000000000000000001"
+    );
+    assert_eq!(
+        calculator.eval("asin 2"),
+        Err(Error::Domain("asin is undefined at 2".to_string()))
+    );
+    assert!(matches!(
+        calculator.eval("tan(pi/2)"),
+        Err(Error::Domain(_))
+    ));
+}
+
+#[test]
+fn test_gcd_lcm_and_primes() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("gcd(12, 18, 27)"), Ok(integer(3)));
+    assert_eq!(calculator.eval("lcm(4, 6)"), Ok(integer(12)));
+    assert_eq!(exact("gcd(10^20, 2^70)"), "1048576");
+    assert_eq!(calculator.eval("isprime(97)"), Ok(integer(1)));
+    assert_eq!(calculator.eval("isprime(2^61 - 1)"), Ok(integer(1)));
+    assert_eq!(calculator.eval("isprime(2^61 + 1)"), Ok(integer(0)));
+    assert_eq!(calculator.eval("nextprime(100)"), Ok(integer(101)));
+    assert_eq!(calculator.eval("totient(36)"), Ok(integer(12)));
+    assert!(matches!(
+        calculator.eval("totient(0)"),
+        Err(Error::Domain(_))
+    ));
+}
+
+#[test]
+fn test_factor() {
+    let mut calculator = Calculator::new();
+    let factors = calculator.eval("factor(360)").unwrap();
+    assert_eq!(factors.to_string(), "2^3 * 3^2 * 5");
+    assert_eq!(calculator.eval("factor(360) + 1"), Ok(integer(361)));
+    assert_eq!(
+        calculator.eval("factor(-12)").unwrap().to_string(),
+        "-2^2 * 3"
+    );
+    // Large factors are found with Pollard's rho
+    assert_eq!(
+        calculator.eval("factor(2^64 + 1)").unwrap().to_string(),
+        "274177 * 67280421310721"
+    );
+    // Small factors are divided out before the size limit applies
+    assert_eq!(
+        calculator.eval("factor(2^2000)").unwrap().to_string(),
+        "2^2000"
+    );
+    assert_eq!(
+        calculator.eval("factor(2^2000 + 1)"),
+        Err(Error::Domain(
+            "factor: number is too large to factor".to_string()
+        ))
+    );
+    assert!(matches!(
+        calculator.eval("factor(0)"),
+        Err(Error::Domain(_))
+    ));
+}
+
+#[test]
+fn test_modular_arithmetic() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("modpow(3, 200, 1000)"), Ok(integer(1)));
+    assert_eq!(calculator.eval("modpow(-2, 3, 5)"), Ok(integer(2)));
+    assert_eq!(calculator.eval("modinv(3, 7)"), Ok(integer(5)));
+    assert_eq!(calculator.eval("modpow(3, -1, 7)"), Ok(integer(5)));
+    assert!(matches!(
+        calculator.eval("modinv(2, 4)"),
+        Err(Error::Domain(_))
+    ));
+    assert!(matches!(
+        calculator.eval("modpow(2, 3, 0)"),
+        Err(Error::Domain(_))
+    ));
+}
+
+#[test]
+fn test_number_theory_requires_integers() {
+    let mut calculator = Calculator::new();
+    assert_eq!(
+        calculator.eval("gcd(2.5, 5)"),
+        Err(Error::IntegerRequired {
+            name: "gcd".to_string(),
+            found: "2.5".to_string()
+        })
+    );
+    assert!(matches!(
+        calculator.eval("isprime(1/3)"),
+        Err(Error::IntegerRequired { .. })
+    ));
+    // Whole decimals and floats are fine
+    assert_eq!(calculator.eval("gcd(6.0, 1e1)"), Ok(integer(2)));
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Decimal { digits: 20 });
+    assert_eq!(calculator.eval("totient(10)"), Ok(integer(4)));
+}
+
+#[test]
+fn test_double_factorial() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("7!!"), Ok(integer(105)));
+    assert_eq!(calculator.eval("8!!"), Ok(integer(384)));
+    assert_eq!(calculator.eval("0!!"), Ok(integer(1)));
+    assert_eq!(calculator.eval("(-1)!!"), Ok(integer(1)));
+    assert_eq!(calculator.eval("(-3)!!"), Ok(Value::Number(-1.0)));
+    assert_eq!(calculator.eval("5!! == 15"), Ok(integer(1)));
+    assert_eq!(exact("31!!"), "191898783962510625");
+    assert!(matches!(calculator.eval("(-2)!!"), Err(Error::Domain(_))));
+    // Between the integers it continues through the Gamma function
+    assert!((calculator.eval("2.5!!").unwrap().to_f64() - 2.40706945611604).abs() < 1e-12);
+}
+
+#[test]
+fn test_combinatorics() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("ncr(52, 5)"), Ok(integer(2598960)));
+    assert_eq!(calculator.eval("npr(5, 2)"), Ok(integer(20)));
+    assert_eq!(calculator.eval("ncr(5, 7)"), Ok(integer(0)));
+    assert_eq!(calculator.eval("ncr(-3, 2)"), Ok(integer(6)));
+    assert_eq!(calculator.eval("multinomial(2, 3, 4)"), Ok(integer(1260)));
+    assert_eq!(calculator.eval("subfactorial(5)"), Ok(integer(44)));
+    assert_eq!(exact("ncr(100, 50)"), "100891344545564193334812497256");
+    assert_eq!(
+        calculator.eval("ncr(3000, 1500) == 2 * ncr(2999, 1499)"),
+        Ok(integer(1))
+    );
+    assert_eq!(
+        calculator.eval("ncr(10^30, 2)").unwrap().to_string(),
+        "499999999999999999999999999999500000000000000000000000000000"
+    );
+    assert!(matches!(
+        calculator.eval("npr(5, -1)"),
+        Err(Error::Domain(_))
+    ));
+    assert!(matches!(
+        calculator.eval("ncr(2^40, 2^39)"),
+        Err(Error::Domain(_))
+    ));
+    assert!(matches!(
+        calculator.eval("ncr(2.5, 1)"),
+        Err(Error::IntegerRequired { .. })
+    ));
+}
+
+#[test]
+fn test_list_broadcasting() {
+    let mut calculator = Calculator::new();
+    let list = |values: &[i64]| Value::List(values.iter().map(|&n| integer(n)).collect());
+    assert_eq!(
+        calculator.eval("[3, 1, 4, 1, 5]"),
+        Ok(list(&[3, 1, 4, 1, 5]))
+    );
+    assert_eq!(calculator.eval("[1, 2, 3] * 2"), Ok(list(&[2, 4, 6])));
+    assert_eq!(calculator.eval("2 ^ [1, 2, 3]"), Ok(list(&[2, 4, 8])));
+    assert_eq!(calculator.eval("sqrt [4, 9]"), Ok(list(&[2, 3])));
+    assert_eq!(calculator.eval("[1, 2] + [10, 20]"), Ok(list(&[11, 22])));
+    assert_eq!(calculator.eval("[1, 2] == [1, 3]"), Ok(list(&[1, 0])));
+    assert_eq!(calculator.eval("[0, 1] && [1, 1]"), Ok(list(&[0, 1])));
+    assert_eq!(
+        calculator.eval("log([10, 100])").unwrap().to_string(),
+        "[1, 2]"
+    );
+    assert_eq!(
+        calculator.eval("f(x) = x^2; f([1, 2, 3])"),
+        Ok(list(&[1, 4, 9]))
+    );
+    assert_eq!(
+        calculator.eval("[1, 2] + [1, 2, 3]"),
+        Err(Error::LengthMismatch { left: 2, right: 3 })
+    );
+    assert_eq!(
+        calculator.eval("sqrt [-1, 4]"),
+        Err(math_error("square root of negative number encountered"))
+    );
+
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Rational);
+    assert_eq!(
+        calculator.eval("[1, 2] / 3").unwrap().to_string(),
+        "[1/3, 2/3]"
+    );
+}
+
+#[test]
+fn test_list_functions() {
+    let mut calculator = Calculator::new();
+    let list = |values: &[i64]| Value::List(values.iter().map(|&n| integer(n)).collect());
+    calculator.eval("xs = [3, 1, 4, 1, 5]").unwrap();
+    assert_eq!(calculator.eval("xs[1]"), Ok(integer(3)));
+    assert_eq!(calculator.eval("xs[-1]"), Ok(integer(5)));
+    assert_eq!(calculator.eval("[[1, 2], [3, 4]][2][1]"), Ok(integer(3)));
+    assert_eq!(calculator.eval("length(xs)"), Ok(integer(5)));
+    assert_eq!(calculator.eval("length([])"), Ok(integer(0)));
+    assert_eq!(calculator.eval("sort(xs)"), Ok(list(&[1, 1, 3, 4, 5])));
+    assert_eq!(calculator.eval("concat([1], xs[2], [])"), Ok(list(&[1, 1])));
+    assert_eq!(calculator.eval("sum(xs)"), Ok(integer(14)));
+    assert_eq!(calculator.eval("prod(xs)"), Ok(integer(60)));
+    assert_eq!(calculator.eval("sum([[1, 2], [3, 4]])"), Ok(list(&[4, 6])));
+    assert_eq!(calculator.eval("max(xs)"), Ok(integer(5)));
+    assert_eq!(calculator.eval("gcd([12, 18], 27)"), Ok(integer(3)));
+
+    assert_eq!(
+        calculator.eval("xs[6]"),
+        Err(Error::IndexOutOfRange {
+            index: "6".to_string(),
+            length: 5
+        })
+    );
+    assert!(matches!(
+        calculator.eval("xs[1.5]"),
+        Err(Error::IntegerRequired { .. })
+    ));
+    assert!(matches!(
+        calculator.eval("sort(2)"),
+        Err(Error::ListRequired { .. })
+    ));
+    assert!(matches!(
+        syntax_error("[1, 2"),
+        ParseError::UnbalancedBracket { .. }
+    ));
+}
+
+#[test]
+fn test_statistics() {
+    let mut calculator = Calculator::new();
+    calculator.eval("xs = [2, 4, 4, 4, 5, 5, 7, 9]").unwrap();
+    assert_eq!(calculator.eval("mean(xs)"), Ok(integer(5)));
+    assert_eq!(calculator.eval("median(xs)"), Ok(Value::Number(4.5)));
+    assert_eq!(calculator.eval("mode(xs)"), Ok(integer(4)));
+    assert_eq!(calculator.eval("varp(xs)"), Ok(integer(4)));
+    assert_eq!(calculator.eval("stdevp(xs)"), Ok(integer(2)));
+    assert_eq!(calculator.eval("var(xs)"), Ok(Value::Number(32.0 / 7.0)));
+    assert_eq!(calculator.eval("range(xs)"), Ok(integer(7)));
+    assert_eq!(calculator.eval("mode([3, 1, 3, 1])"), Ok(integer(1)));
+    assert_eq!(
+        calculator.eval("percentile([1, 2, 3, 4, 5], 90)"),
+        Ok(Value::Number(4.6))
+    );
+    assert_eq!(
+        calculator.eval("quartiles([1, 2, 3, 4, 5, 6, 7, 8, 9])"),
+        Ok(Value::List(vec![integer(3), integer(5), integer(7)]))
+    );
+    assert_eq!(
+        calculator.eval("zscore([1, 2, 3])"),
+        Ok(Value::List(vec![integer(-1), integer(0), integer(1)]))
+    );
+    assert_eq!(calculator.eval("zscore(4, [1, 2, 3])"), Ok(integer(2)));
+    // Exact sums, so a large offset doesn't swamp the spread
+    assert_eq!(
+        calculator.eval("var([1e9 + 4, 1e9 + 7, 1e9 + 13, 1e9 + 16])"),
+        Ok(integer(30))
+    );
+
+    calculator.settings_mut().mode = Mode::Rational;
+    assert_eq!(calculator.eval("var(xs)"), Ok(fraction(32, 7)));
+
+    assert!(matches!(calculator.eval("var([1])"), Err(Error::Domain(_))));
+    assert!(matches!(calculator.eval("mean([])"), Err(Error::Domain(_))));
+    assert!(matches!(
+        calculator.eval("percentile(xs, 101)"),
+        Err(Error::Domain(_))
+    ));
+    assert!(matches!(
+        calculator.eval("mean(5)"),
+        Err(Error::ListRequired { .. })
+    ));
+}
+
+#[test]
+fn test_matrices() {
+    let mut calculator = Calculator::new();
+    let matrix = |rows: &[&[i64]]| {
+        Value::List(
+            rows.iter()
+                .map(|row| Value::List(row.iter().map(|&n| integer(n)).collect()))
+                .collect(),
+        )
+    };
+    calculator.eval("a = [[1, 2], [3, 4]]").unwrap();
+    assert_eq!(calculator.eval("det(a)"), Ok(integer(-2)));
+    assert_eq!(calculator.eval("trace(a)"), Ok(integer(5)));
+    assert_eq!(calculator.eval("rank([[1, 2], [2, 4]])"), Ok(integer(1)));
+    assert_eq!(
+        calculator.eval("transpose([[1, 2, 3], [4, 5, 6]])"),
+        Ok(matrix(&[&[1, 4], &[2, 5], &[3, 6]]))
+    );
+    assert_eq!(
+        calculator.eval("a @ [[5, 6], [7, 8]]"),
+        Ok(matrix(&[&[19, 22], &[43, 50]]))
+    );
+    assert_eq!(
+        calculator.eval("a @ inv(a)").unwrap().to_string(),
+        "[[1, 0], [0, 1]]"
+    );
+    assert_eq!(
+        calculator.eval("identity(2)"),
+        Ok(matrix(&[&[1, 0], &[0, 1]]))
+    );
+    // Lists are rows on the left of @ and columns on the right
+    assert_eq!(
+        calculator.eval("a @ [1, 1]"),
+        Ok(Value::List(vec![integer(3), integer(7)]))
+    );
+    assert_eq!(calculator.eval("[1, 2, 3] @ [4, 5, 6]"), Ok(integer(32)));
+    assert_eq!(
+        calculator.eval("linsolve([[2, 1], [1, 3]], [3, 5])"),
+        Ok(Value::List(vec![Value::Number(0.8), Value::Number(1.4)]))
+    );
+
+    let mut calculator = with_settings(|settings| settings.mode = Mode::Rational);
+    assert_eq!(
+        calculator.eval("inv([[1, 2], [3, 4]])"),
+        Ok(Value::List(vec![
+            Value::List(vec![fraction(-2, 1), fraction(1, 1)]),
+            Value::List(vec![fraction(3, 2), fraction(-1, 2)]),
+        ]))
+    );
+}
+
+#[test]
+fn test_matrix_errors() {
+    let mut calculator = Calculator::new();
+    assert_eq!(
+        calculator.eval("[[1, 2, 3]] @ [[1, 2]]"),
+        Err(Error::DimensionMismatch {
+            operation: "@".to_string(),
+            left: "1x3".to_string(),
+            right: "1x2".to_string(),
+        })
+    );
+    assert_eq!(
+        calculator.eval("linsolve([[1, 2], [3, 4]], [1, 2, 3])"),
+        Err(Error::DimensionMismatch {
+            operation: "linsolve".to_string(),
+            left: "2x2".to_string(),
+            right: "3x1".to_string(),
+        })
+    );
+    assert_eq!(
+        calculator.eval("det([[1, 2, 3], [4, 5, 6]])"),
+        Err(Error::SquareRequired {
+            name: "det".to_string(),
+            found: "2x3".to_string(),
+        })
+    );
+    assert!(matches!(
+        calculator.eval("trace([[1, 2], [3]])"),
+        Err(Error::MatrixRequired { .. })
+    ));
+    assert!(matches!(
+        calculator.eval("2 @ 3"),
+        Err(Error::MatrixRequired { .. })
+    ));
+    assert!(matches!(
+        calculator.eval("inv([[1, 2], [2, 4]])"),
+        Err(Error::Domain(_))
+    ));
+    assert!(matches!(
+        calculator.eval("identity(0)"),
+        Err(Error::Domain(_))
+    ));
+}
+
+#[test]
+fn test_sums_and_products() {
+    let mut calculator = Calculator::new();
+    assert_eq!(calculator.eval("sum(k, 1, 100, k^2)"), Ok(integer(338350)));
+    assert_eq!(
+        with_settings(|settings| settings.mode = Mode::Rational).eval("prod(k, 1, 10, 1 + 1/k)"),
+        Ok(fraction(11, 1))
+    );
+    assert_eq!(calculator.eval("sum(k, 1, 0, k)"), Ok(integer(0)));
+    assert_eq!(calculator.eval("prod(k, 1, 0, k)"), Ok(integer(1)));
+    assert_eq!(
+        calculator.eval("sum(j, 1, 3, sum(k, 1, j, k))"),
+        Ok(integer(10))
+    );
+    // The index shadows a variable only inside the sum, and sees parameters around it
+    assert_eq!(
+        calculator.eval("k = 5; sum(k, 1, 3, k) + k"),
+        Ok(integer(11))
+    );
+    assert_eq!(
+        calculator.eval("f(n) = sum(k, 1, n, k * n); f(3)"),
+        Ok(integer(18))
+    );
+    assert_eq!(calculator.eval("sum([1, 2, 3])"), Ok(integer(6)));
+
+    assert_eq!(
+        calculator.eval("sum(k, 1, 10^7, k)"),
+        Err(Error::IterationLimit)
+    );
+    // Terms of nested sums count towards the same limit
+    assert_eq!(
+        calculator.eval("sum(j, 1, 1000, sum(k, 1, 1000, j * k))"),
+        Err(Error::IterationLimit)
+    );
+    assert!(matches!(
+        calculator.eval("sum(k, 1.5, 3, k)"),
+        Err(Error::IntegerRequired { .. })
+    ));
+    assert_eq!(
+        syntax_error("sum(pi, 1, 3, pi)"),
+        ParseError::ReservedName {
+            name: "pi".to_string(),
+            span: 4..6
+        }
+    );
+    assert!(matches!(
+        syntax_error("prod(k, 1, 3)"),
+        ParseError::WrongArgumentCount { found: 3, .. }
+    ));
+    assert!(matches!(
+        syntax_error("sum(1 + 1, 1, 3, 2)"),
+        ParseError::IndexNotName { .. }
+    ));
+}
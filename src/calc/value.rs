@@ -0,0 +1,119 @@
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+
+use crate::calc::complex;
+use crate::calc::decimal::Decimal;
+use crate::calc::number_theory::Factors;
+
+/// Result of evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    /// Whole number of any size, kept exact until an operation needs a fraction.
+    Integer(BigInt),
+    /// Exact fraction, only produced in [`Mode::Rational`](crate::Mode::Rational).
+    Rational(BigRational),
+    /// High precision number, only produced in [`Mode::Decimal`](crate::Mode::Decimal).
+    Decimal(Decimal),
+    /// Number with a non-zero imaginary part, only produced when
+    /// [`Settings::complex`](crate::Settings::complex) is set.
+    Complex(Complex64),
+    /// Prime factorisation from `factor`, used as the number itself in calculations.
+    Factors(Factors),
+    /// List like `[3, 1, 4]`. Operators and most functions apply to each element.
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Number(n) => *n,
+            Value::Integer(n) => n.to_f64().unwrap_or(f64::NAN),
+            Value::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Value::Decimal(d) => d.to_f64(),
+            Value::Complex(_) => f64::NAN, // Has no real value
+            Value::Factors(factors) => factors.value().to_f64().unwrap_or(f64::NAN),
+            Value::List(_) => f64::NAN, // Not a single number
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        match self {
+            Value::Number(n) => n.is_nan(),
+            Value::Complex(z) => z.is_nan(),
+            Value::List(elements) => elements.iter().any(Value::is_nan),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn to_complex(&self) -> Complex64 {
+        match self {
+            Value::Complex(z) => *z,
+            _ => self.to_f64().into(),
+        }
+    }
+
+    // Exact for fractions, so 1e-400 is not mistaken for false
+    pub(crate) fn is_zero(&self) -> bool {
+        match self {
+            Value::Number(n) => *n == 0.0,
+            Value::Integer(n) => n.is_zero(),
+            Value::Rational(r) => r.is_zero(),
+            Value::Decimal(d) => d.is_zero(),
+            Value::Complex(z) => z.is_zero(),
+            Value::Factors(_) | Value::List(_) => false,
+        }
+    }
+
+    // The exact value of a whole number, so 2.5 and 1e-400 are not mistaken for one
+    pub(crate) fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 => BigInt::from_f64(*n),
+            Value::Number(_) => None,
+            Value::Integer(n) => Some(n.clone()),
+            Value::Rational(r) => r.is_integer().then(|| r.to_integer()),
+            Value::Decimal(d) => d.to_integer(),
+            Value::Complex(_) | Value::List(_) => None,
+            Value::Factors(factors) => Some(factors.value()),
+        }
+    }
+
+    // Factorisations are only kept for showing, calculations use the number itself
+    pub(crate) fn expand(self) -> Value {
+        match self {
+            Value::Factors(factors) => Value::Integer(factors.value()),
+            value => value,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Whole numbers print without a fractional part, e.g. 5 rather than 5.0
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                write!(f, "{}", *n as i64)
+            }
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Rational(r) => write!(f, "{}", r), // 3/10, or just 3 for whole numbers
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Complex(z) => write!(f, "{}", complex::rectangular(z)),
+            Value::Factors(factors) => write!(f, "{}", factors),
+            Value::List(elements) => {
+                let elements: Vec<String> = elements.iter().map(Value::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Expression parser and evaluator behind the `calc` terminal calculator.
+//!
+//! ```
+//! use calc::{Calculator, Value};
+//!
+//! let mut calculator = Calculator::new();
+//! calculator.eval("r = 3").unwrap();
+//! assert_eq!(calculator.eval("2r + 1").unwrap(), Value::Integer(7.into()));
+//! ```
+
+mod calc {
+    pub mod calculator;
+    pub mod combinatorics;
+    pub mod complex;
+    pub mod context;
+    pub mod decimal;
+    pub mod error;
+    pub mod integer;
+    pub mod matrix;
+    pub mod number_theory;
+    pub mod parser;
+    pub mod rational;
+    pub mod settings;
+    pub mod special;
+    pub mod statistics;
+    pub mod value;
+
+    #[cfg(test)]
+    mod tests;
+}
+
+pub use calc::calculator::Calculator;
+pub use calc::decimal::{Decimal, MAX_PRECISION};
+pub use calc::error::{Error, ParseError, Span};
+pub use calc::number_theory::Factors;
+pub use calc::settings::{AngleUnit, ComplexFormat, Format, Mode, Settings};
+pub use calc::value::Value;
+
+/// Syntax tree produced by [`Calculator::parse`], one [`ast::Token`] per statement.
+pub mod ast {
+    pub use crate::calc::parser::{
+        AssignmentToken, BinaryOperator, BinaryToken, CallToken, Constant, DefinitionToken,
+        Function, IndexToken, SeriesToken, Token, UnaryOperator, UnaryToken, UserCallToken,
+    };
+}
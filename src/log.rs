@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use colored::Colorize;
 
 #[derive(Debug, Clone, Copy)]
@@ -16,3 +18,23 @@ pub fn log(level: Level, message: &str) {
         }
     }
 }
+
+// Logs an error followed by the input line containing the start of `span`, with a caret
+// underline below the part of `span` on that line
+pub fn log_diagnostic(input: &str, span: Range<usize>, message: &str) {
+    log(Level::Error, message);
+
+    // End-of-input spans point one past the last character, so clamp them
+    let start = span.start.min(input.len());
+    let line_start = input[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+    let line = &input[line_start..line_end];
+
+    // Columns are counted in characters so the caret lines up with non-ASCII input
+    let end = span.end.clamp(start, line_end);
+    let column = input[line_start..start].chars().count();
+    let width = input[start..end].chars().count().max(1);
+
+    eprintln!("  {}", line);
+    eprintln!("  {}{}", " ".repeat(column), "^".repeat(width).red().bold());
+}
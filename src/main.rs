@@ -1,20 +1,13 @@
 use std::process::exit;
 
-use crate::calc::calculator::evaluate;
-use crate::log::{Level, log};
-
-mod calc {
-    pub mod calculator;
-    pub mod parser;
-
-    #[cfg(test)]
-    mod tests;
-}
-
 mod log;
 
+use calc::{
+    AngleUnit, Calculator, ComplexFormat, Error, Format, MAX_PRECISION, Mode, Settings, Value,
+};
 use clap::Parser;
 use colored::Colorize;
+use log::{Level, log, log_diagnostic};
 use rustyline::{self, DefaultEditor, error::ReadlineError};
 
 #[derive(Parser)]
@@ -23,6 +16,30 @@ struct Cli {
     /// evaluates expression from command line instead of interactive mode
     #[arg(short, long)]
     input: Option<String>,
+
+    /// prints the result of every statement (separated by ';' or newlines) instead of only the last
+    #[arg(short, long)]
+    all: bool,
+
+    /// number representation: float, or rational for exact fractions
+    #[arg(short, long, value_parser = ["float", "rational"])]
+    mode: Option<String>,
+
+    /// how fractions are shown in rational mode: fraction or decimal
+    #[arg(short, long, value_parser = ["fraction", "decimal"])]
+    format: Option<String>,
+
+    /// evaluates with this many significant decimal digits instead of floating point
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..=MAX_PRECISION as u64))]
+    precision: Option<u64>,
+
+    /// allows complex results, shown as rect (3 + 4i) or polar; off keeps results real
+    #[arg(short, long, value_parser = ["off", "rect", "polar"])]
+    complex: Option<String>,
+
+    /// unit of angles for trig functions: rad, deg or grad
+    #[arg(long, value_parser = ["rad", "deg", "grad"])]
+    angle: Option<String>,
 }
 
 fn format_example(expression: &str, result: &str) -> String {
@@ -36,14 +53,46 @@ fn format_example(expression: &str, result: &str) -> String {
 
 fn show_help() {
     println!("{}", "Available commands:".cyan().bold());
-    println!("  {} - {}", "help".green(), "Show this help message");
-    println!("  {} - {}", "quit".green(), "Exit the program");
+    println!("  {} - Show this help message", "help".green());
+    println!("  {} - Exit the program", "quit".green());
+    println!(
+        "  {} - Show every statement's result or only the last",
+        ":set results all|last".green()
+    );
+    println!(
+        "  {} - Use floating point or exact fractions",
+        ":set mode float|rational".green()
+    );
+    println!(
+        "  {} - Show fractions as 3/10 or 0.3",
+        ":set format fraction|decimal".green()
+    );
+    println!(
+        "  {} - Use decimals with this many significant digits",
+        ":set precision <digits>".green()
+    );
+    println!(
+        "  {} - Allow complex results like sqrt(-4) and how to show them",
+        ":set complex off|rect|polar".green()
+    );
+    println!(
+        "  {} - Unit of angles for trig functions",
+        ":set angle rad|deg|grad".green()
+    );
+    println!("  {} - List user-defined functions", ":functions".green());
+    println!(
+        "  {} - Remove a user-defined function",
+        ":remove <name>".green()
+    );
     println!();
     println!("{}", "Usage:".cyan().bold());
     println!(
-        "  {} - {}",
-        "<expression>".yellow(),
-        "Calculate the result of the expression"
+        "  {} - Calculate the result of the expression",
+        "<expression>".yellow()
+    );
+    println!(
+        "  {} - Separate statements with ';' or a newline, comments start with '#'",
+        "<expr>; <expr> # note".yellow()
     );
     println!();
     println!("{}", "Examples:".cyan().bold());
@@ -51,28 +100,114 @@ fn show_help() {
     println!("{}", format_example("(2 + 3) * 4", "20"));
     println!("{}", format_example("sqrt 16", "4"));
     println!("{}", format_example("sin pi", "0"));
+    println!("{}", format_example("sin 90°", "1"));
     println!("{}", format_example("2 * pi", "6.283185"));
     println!("{}", format_example("e ^ 2", "7.389056"));
+    println!("{}", format_example("log(100, 10)", "2"));
+    println!("{}", format_example("max(3, 7, 5)", "7"));
+    println!("{}", format_example("[1, 2, 3] * 2", "[2, 4, 6]"));
+    println!("{}", format_example("mean([2, 4, 9])", "5"));
+    println!("{}", format_example("sum(k, 1, 100, k^2)", "338350"));
+    println!("{}", format_example("det([[1, 2], [3, 4]])", "-2"));
+    println!("{}", format_example("factor(360)", "2^3 * 3^2 * 5"));
+    println!("{}", format_example("ncr(52, 5)", "2598960"));
+    println!("{}", format_example("2 + 3; * 4 # reuse", "20"));
+    println!("{}", format_example("r = 3; pi * r^2", "28.274334"));
+    println!("{}", format_example("f(x) = x^2 + 1; f(3)", "10"));
+    println!("{}", format_example("sqrt(-4) # :set complex rect", "2i"));
+}
+
+fn print_results(results: &[Value], calculator: &Calculator) {
+    let shown = match results.last() {
+        Some(_) if !calculator.settings().show_all => &results[results.len() - 1..],
+        _ => results,
+    };
+    for res in shown {
+        println!("{}", calculator.format(res));
+    }
+}
+
+// Handles `:set <name> <value>` in interactive mode
+fn apply_setting(settings: &mut Settings, args: &str) -> Result<(), String> {
+    let mut words = args.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("results"), Some("all"), None) => settings.show_all = true,
+        (Some("results"), Some("last"), None) => settings.show_all = false,
+        (Some("results"), _, _) => return Err("Usage: :set results all|last".to_string()),
+        (Some("mode"), Some("float"), None) => settings.mode = Mode::Float,
+        (Some("mode"), Some("rational"), None) => settings.mode = Mode::Rational,
+        (Some("mode"), _, _) => return Err("Usage: :set mode float|rational".to_string()),
+        (Some("format"), Some("fraction"), None) => settings.format = Format::Fraction,
+        (Some("format"), Some("decimal"), None) => settings.format = Format::Decimal,
+        (Some("format"), _, _) => return Err("Usage: :set format fraction|decimal".to_string()),
+        (Some("precision"), Some(digits), None) if let Ok(digits @ 1..) = digits.parse::<u64>() => {
+            if digits > MAX_PRECISION as u64 {
+                return Err(format!("Precision is limited to {MAX_PRECISION} digits"));
+            }
+            settings.mode = Mode::Decimal {
+                digits: digits as usize,
+            }
+        }
+        (Some("precision"), _, _) => return Err("Usage: :set precision <digits>".to_string()),
+        (Some("complex"), Some("off"), None) => settings.complex = None,
+        (Some("complex"), Some("rect"), None) => {
+            settings.complex = Some(ComplexFormat::Rectangular)
+        }
+        (Some("complex"), Some("polar"), None) => settings.complex = Some(ComplexFormat::Polar),
+        (Some("complex"), _, _) => return Err("Usage: :set complex off|rect|polar".to_string()),
+        (Some("angle"), Some("rad"), None) => settings.angle = AngleUnit::Radians,
+        (Some("angle"), Some("deg"), None) => settings.angle = AngleUnit::Degrees,
+        (Some("angle"), Some("grad"), None) => settings.angle = AngleUnit::Gradians,
+        (Some("angle"), _, _) => return Err("Usage: :set angle rad|deg|grad".to_string()),
+        (Some(name), _, _) => return Err(format!("Unknown setting: {name}")),
+        (None, _, _) => return Err("Usage: :set <name> <value>".to_string()),
+    }
+    Ok(())
 }
 
-fn format_result(res: f64) -> String {
-    match res {
-        r if r.fract() == 0.0 => format!("{}", r as i64),
-        r => format!("{}", r),
+// Logs an evaluation error, underlining the offending input for syntax errors
+fn report_error(input: &str, err: &Error) {
+    match err {
+        Error::Syntax(e) => log_diagnostic(input, e.span(), &err.to_string()),
+        _ => log(Level::Error, &err.to_string()),
     }
 }
 
 fn main() {
     let cli = Cli::parse();
+    let mut calculator = Calculator::new();
+    calculator.settings_mut().show_all = cli.all;
+    // Command line options take the same values as the matching :set commands
+    let precision = cli.precision.map(|digits| digits.to_string());
+    let options = [
+        ("mode", &cli.mode),
+        ("format", &cli.format),
+        ("precision", &precision),
+        ("complex", &cli.complex),
+        ("angle", &cli.angle),
+    ];
+    for (name, value) in options {
+        if let Some(value) = value
+            && let Err(message) =
+                apply_setting(calculator.settings_mut(), &format!("{name} {value}"))
+        {
+            log(Level::Error, &message);
+            exit(1);
+        }
+    }
 
     // If an input expression is provided via CLI, evaluate it and exit
     if let Some(input) = cli.input.as_deref() {
-        match evaluate(input.to_string()) {
-            Ok(res) => {
-                println!("{}", format_result(res));
-                exit(!res.is_nan() as i32);
+        // Input with only definitions has no result and prints nothing
+        match calculator.eval_all(input) {
+            Ok(results) => {
+                print_results(&results, &calculator);
+                exit(0);
+            }
+            Err(e) => {
+                report_error(input, &e);
+                exit(1);
             }
-            Err(_) => exit(1),
         }
     } else {
         let mut editor = DefaultEditor::new().unwrap_or_else(|_| {
@@ -93,13 +228,33 @@ fn main() {
                     match line.trim() {
                         "help" | "h" => show_help(),
                         "quit" | "q" => exit(0),
+                        ":functions" => {
+                            let definitions = calculator.function_definitions();
+                            if definitions.is_empty() {
+                                println!("No functions defined");
+                            }
+                            for definition in definitions {
+                                println!("{}", definition);
+                            }
+                        }
+                        command if command.starts_with(":remove") => {
+                            let name = command[7..].trim();
+                            if !calculator.remove_function(name) {
+                                log(Level::Error, &format!("Unknown function: {name}"));
+                            }
+                        }
+                        command if command.starts_with(":set") => {
+                            if let Err(message) =
+                                apply_setting(calculator.settings_mut(), &command[4..])
+                            {
+                                log(Level::Error, &message);
+                            }
+                        }
                         _ => {
-                            match evaluate(line) {
-                                Ok(res) => {
-                                    println!("{}", format_result(res));
-                                }
+                            match calculator.eval_all(&line) {
+                                Ok(results) => print_results(&results, &calculator),
                                 Err(e) => {
-                                    log(Level::Error, &format!("{:?}", e));
+                                    report_error(&line, &e);
                                     continue;
                                 }
                             };